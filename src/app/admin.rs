@@ -0,0 +1,106 @@
+use super::export::delete_exports;
+use crate::{
+    db::{get_mongo, PaginationOptions},
+    models::{
+        Admin, Media, Resource, Sessions, UserDisableReq, UserInfo, UserPasswordReq, UserSearchReq,
+    },
+    tools::{validate_password, PasswordBlocklist, ResourceIOError, SeaweedFsId, UserError},
+};
+use actix_web::{web, HttpResponse};
+use mongodb::bson::oid::ObjectId;
+use std::sync::RwLock;
+
+type AdminResponse = Result<HttpResponse, UserError>;
+
+pub fn config_admin(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .route("/users", web::get().to(list_users))
+            .route("/users/{id}/disable", web::post().to(disable_user))
+            .route("/users/{id}/password", web::post().to(reset_user_password))
+            .route("/media/{id}", web::get().to(get_resource))
+            .route("/media/{id}", web::delete().to(delete_resource))
+            .route("/storage", web::get().to(get_storage_usage)),
+    );
+}
+
+pub async fn list_users(
+    _admin: Admin,
+    search: web::Query<UserSearchReq>,
+    pagination: web::Query<PaginationOptions>,
+) -> AdminResponse {
+    let db = get_mongo().await;
+    let users = db.find_users(search.q.as_deref(), &pagination).await?;
+    let users: Vec<UserInfo> = users.iter().map(UserInfo::from).collect();
+    Ok(HttpResponse::Ok().json(users))
+}
+
+pub async fn disable_user(
+    _admin: Admin,
+    path: web::Path<String>,
+    req: web::Json<UserDisableReq>,
+    sessions: web::Data<RwLock<Sessions>>,
+) -> AdminResponse {
+    let db = get_mongo().await;
+    let user = db
+        .get_user_by_id(&ObjectId::with_string(&path).map_err(|_| UserError::UnknownUser)?)
+        .await?
+        .ok_or(UserError::UnknownUser)?;
+    db.set_user_disabled(&user.get_id().unwrap(), req.disabled)
+        .await?;
+    if req.disabled {
+        sessions.write().unwrap().map.remove(&user.get_username());
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn reset_user_password(
+    _admin: Admin,
+    path: web::Path<String>,
+    req: web::Json<UserPasswordReq>,
+    sessions: web::Data<RwLock<Sessions>>,
+    blocklist: web::Data<PasswordBlocklist>,
+) -> AdminResponse {
+    validate_password(&req.new_password, &blocklist)?;
+    let db = get_mongo().await;
+    let mut user = db
+        .get_user_by_id(&ObjectId::with_string(&path).map_err(|_| UserError::UnknownUser)?)
+        .await?
+        .ok_or(UserError::UnknownUser)?;
+    user.set_password(&req.new_password);
+    db.set_credential(&user).await?;
+    sessions.write().unwrap().map.remove(&user.get_username());
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_resource(_admin: Admin, path: web::Path<String>) -> AdminResponse {
+    let db = get_mongo().await;
+    let res: Resource<SeaweedFsId> = db
+        .find_resource(&ObjectId::with_string(&path).map_err(|_| ResourceIOError::NotFound)?)
+        .await?
+        .ok_or(ResourceIOError::NotFound)?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+pub async fn delete_resource(admin: Admin, path: web::Path<String>) -> AdminResponse {
+    let db = get_mongo().await;
+    let res: Resource<SeaweedFsId> = db
+        .find_resource(&ObjectId::with_string(&path).map_err(|_| ResourceIOError::NotFound)?)
+        .await?
+        .ok_or(ResourceIOError::NotFound)?;
+    if !res.can_delete(Some(&admin.0)) {
+        return Err(ResourceIOError::InsufficientPermissions("deleting".to_string()).into());
+    }
+    //Resources go through the trash, whoever deletes them
+    if !db.trash_resource(res.get_id().unwrap()).await? {
+        return Err(ResourceIOError::NotFound.into());
+    }
+    //Exports of the owner would still hold a copy of the removed media
+    delete_exports(&res.get_owner()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_storage_usage(_admin: Admin) -> AdminResponse {
+    let db = get_mongo().await;
+    Ok(HttpResponse::Ok().json(db.storage_usage().await?))
+}
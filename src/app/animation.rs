@@ -0,0 +1,149 @@
+use super::media::{discard, read_all, store_blob, BlobData};
+use super::watermark::watermarked;
+use crate::{
+    config::Config,
+    db::get_mongo,
+    models::{Media, Readable, Resource, User},
+    tools::{convert_to_webp, ResourceIOError, ResponseStream, SeaweedFsId},
+};
+use actix_web::{web, HttpResponse};
+use mongodb::bson::oid::ObjectId;
+use ring::digest;
+use serde::Deserialize;
+use std::path::Path;
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+pub fn config_animation(cfg: &mut web::ServiceConfig) {
+    cfg.route("{id}/preview", web::get().to(get_preview))
+        .route("{id}/convert", web::post().to(convert_media));
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConvertFormat {
+    Webp,
+}
+
+#[derive(Deserialize)]
+pub struct ConvertReq {
+    format: ConvertFormat,
+}
+
+async fn find_media(id: &str) -> Result<Resource<SeaweedFsId>, ResourceIOError> {
+    let id = ObjectId::with_string(id).map_err(|_| ResourceIOError::NotFound)?;
+    get_mongo()
+        .await
+        .find_resource(&id)
+        .await?
+        .filter(|r| !r.is_trashed())
+        .ok_or(ResourceIOError::NotFound)
+}
+
+///Smaller animated version of a large animation
+pub async fn get_preview(
+    path: web::Path<String>,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    let mut res = find_media(&path).await?;
+    if !res.can_read(Some(&user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "reading".to_string(),
+        ));
+    }
+    //Animations of others are replaced by their watermarked still
+    if let Some(storage) = watermarked(&mut res, &user, &config).await? {
+        return Ok(HttpResponse::Ok()
+            .content_type(res.rendered_mime().essence_str())
+            .streaming(ResponseStream {
+                stream: storage.read().await,
+            }));
+    }
+    let preview = res.get_preview().ok_or(ResourceIOError::NotFound)?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/webp")
+        .streaming(ResponseStream {
+            stream: preview.read().await,
+        }))
+}
+
+///Replace an animated GIF by the same animation as a WebP, only when it is lighter.
+///Only the owner or an admin can do it as the original data is deleted
+pub async fn convert_media(
+    path: web::Path<String>,
+    user: User,
+    req: web::Json<ConvertReq>,
+) -> ResourceResponse {
+    let mut res = find_media(&path).await?;
+    if !res.can_delete(Some(&user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "converting".to_string(),
+        ));
+    }
+    match req.format {
+        ConvertFormat::Webp => {
+            if res.get_extension() != &mime::IMAGE_GIF || res.get_animation().is_none() {
+                return Err(ResourceIOError::UnsupportedMediaType(
+                    "animated image/gif".to_string(),
+                ));
+            }
+        }
+    }
+
+    let data = read_all(res.read(Some(&user)).await?).await?;
+    let (original_size, converted) = tokio::task::spawn_blocking(move || {
+        let converted = convert_to_webp(&data);
+        (data.len(), converted)
+    })
+    .await
+    .unwrap();
+    let converted = converted
+        .ok_or_else(|| ResourceIOError::InvalidUpload("animation cannot be decoded".to_string()))?;
+    if converted.len() >= original_size {
+        return Err(ResourceIOError::NoSpaceSaved);
+    }
+
+    let db = get_mongo().await;
+    let previous = res.take_storage();
+    let previous_hash = res.get_hash().map(str::to_string);
+    let previous_size = res.get_size();
+    let size = converted.len() as i64;
+    let hash = hex::encode(digest::digest(&digest::SHA256, &converted));
+    store_blob(&mut res, &user, &hash, BlobData::Memory(converted), size).await?;
+    res.set_hash(hash);
+    res.set_size(size);
+    res.set_extension("image/webp".parse().unwrap());
+    if let Some(filename) = res
+        .get_filename()
+        .map(|f| Path::new(f).with_extension("webp"))
+    {
+        res.set_filename(&filename.to_string_lossy());
+    }
+    if !db.replace_data(&res, previous_hash.as_deref()).await? {
+        //The data changed during the conversion, which is not needed anymore
+        if db
+            .release_blob::<SeaweedFsId>(res.get_hash().unwrap())
+            .await?
+        {
+            if let Some(storage) = res.get_storage() {
+                discard(storage).await;
+            }
+        }
+        return Err(ResourceIOError::EditConflict);
+    }
+    db.add_storage_used(&res.get_owner(), size - previous_size)
+        .await?;
+
+    //The GIF is deleted once no other resource references it
+    let unused = match previous_hash {
+        Some(hash) => db.release_blob::<SeaweedFsId>(&hash).await?,
+        None => true,
+    };
+    if unused {
+        if let Some(storage) = previous {
+            discard(&storage).await;
+        }
+    }
+    Ok(HttpResponse::Ok().json(res))
+}
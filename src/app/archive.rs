@@ -0,0 +1,161 @@
+use super::watermark::watermarked;
+use crate::{
+    config::Config,
+    db::get_mongo,
+    models::{Media, Resource, User},
+    tools::{ResourceIOError, SeaweedFsId, ZipWriter, ZIP_MAX_ENTRY_SIZE},
+};
+use actix_web::{http::header, web, HttpResponse};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::StreamExt;
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+};
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+const MAX_ARCHIVE_ITEMS: usize = 1000;
+
+pub fn config_archive(cfg: &mut web::ServiceConfig) {
+    cfg.route("/archive", web::get().to(get_archive));
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveQuery {
+    ///Comma separated ids
+    ids: String,
+}
+
+///Unique name of res in an archive, its original name when known
+fn archive_name(res: &Resource<SeaweedFsId>, used: &mut HashSet<String>) -> String {
+    let name = match res.get_filename() {
+        Some(filename) => sanitize_filename::sanitize(filename),
+        None => format!(
+            "{}.{}",
+            res.get_id().unwrap().to_hex(),
+            res.get_extension().subtype()
+        ),
+    };
+    let (stem, extension) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name.as_str(), ""),
+    };
+    let mut unique = name.clone();
+    let mut n = 1;
+    while used.contains(&unique.to_lowercase()) {
+        unique = format!("{} ({}){}", stem, n, extension);
+        n += 1;
+    }
+    used.insert(unique.to_lowercase());
+    unique
+}
+
+///Pair resources with unique names within an archive
+pub fn name_entries(resources: Vec<Resource<SeaweedFsId>>) -> Vec<(String, Resource<SeaweedFsId>)> {
+    let mut used = HashSet::new();
+    resources
+        .into_iter()
+        .map(|res| (archive_name(&res, &mut used), res))
+        .collect()
+}
+
+///Send a ZIP archive of named resources followed by extra files chunk by chunk,
+///reading each resource as user. Images of others are archived with the watermark of their
+///owner, or left out when it cannot be drawn. Stops early without error once nobody receives
+///chunks anymore
+pub async fn stream_archive(
+    entries: Vec<(String, Resource<SeaweedFsId>)>,
+    extra: Vec<(String, Bytes)>,
+    user: &User,
+    config: &Config,
+    tx: &Sender<Result<Bytes, io::Error>>,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::default();
+    for (name, mut res) in entries {
+        //Only this copy of the resource points to the watermarked one
+        match watermarked(&mut res, user, config).await {
+            Ok(Some(storage)) => res.set_storage(storage),
+            Ok(None) => {}
+            Err(ResourceIOError::WatermarkUnavailable) => continue,
+            Err(e) => return Err(io::Error::other(e)),
+        }
+        let mut stream = res.read(Some(user)).await.map_err(io::Error::other)?;
+        let header = zip.start_entry(&name, res.get_date().unwrap_or_else(Utc::now));
+        if tx.send(Ok(header)).await.is_err() {
+            return Ok(());
+        }
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(io::Error::other)?;
+            zip.entry_data(&chunk);
+            if tx.send(Ok(chunk)).await.is_err() {
+                return Ok(());
+            }
+        }
+        if tx.send(Ok(zip.finish_entry())).await.is_err() {
+            return Ok(());
+        }
+    }
+    for (name, data) in extra {
+        let header = zip.start_entry(&name, Utc::now());
+        zip.entry_data(&data);
+        for chunk in [header, data, zip.finish_entry()] {
+            if tx.send(Ok(chunk)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    let _ = tx.send(Ok(zip.finish())).await;
+    Ok(())
+}
+
+///ZIP archive of the requested media, built while it is downloaded.
+///Media the user cannot read are left out
+pub async fn get_archive(
+    user: User,
+    query: web::Query<ArchiveQuery>,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    let ids: Vec<ObjectId> = query
+        .ids
+        .split(',')
+        .filter_map(|id| ObjectId::with_string(id.trim()).ok())
+        .collect();
+    if ids.is_empty() || ids.len() > MAX_ARCHIVE_ITEMS {
+        return Err(ResourceIOError::InvalidQuery(format!(
+            "between 1 and {} ids are required",
+            MAX_ARCHIVE_ITEMS
+        )));
+    }
+    let db = get_mongo().await;
+    let mut found: HashMap<ObjectId, Resource<SeaweedFsId>> = db
+        .find_resources::<SeaweedFsId>(&ids)
+        .await?
+        .into_iter()
+        .filter(|r| !r.is_trashed() && r.can_read(Some(&user)))
+        .filter(|r| (r.get_size() as u64) < ZIP_MAX_ENTRY_SIZE)
+        .map(|r| (r.get_id().unwrap().clone(), r))
+        .collect();
+    //Keep the requested order, each media once
+    let resources = ids.iter().filter_map(|id| found.remove(id)).collect();
+    let entries = name_entries(resources);
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    actix_web::rt::spawn(async move {
+        if let Err(e) = stream_archive(entries, Vec::new(), &user, &config, &tx).await {
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"pixure.zip\"",
+        ))
+        .streaming(ReceiverStream::new(rx)))
+}
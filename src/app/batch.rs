@@ -0,0 +1,171 @@
+use crate::{
+    db::get_mongo,
+    models::{
+        normalize_tag, BatchItemResult, BatchOperation, BatchReport, BatchReq, BatchStatus, Media,
+        Resource, User, TAGS_MAX,
+    },
+    tools::{ResourceIOError, SeaweedFsId},
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use std::collections::HashMap;
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+const MAX_BATCH_SIZE: usize = 500;
+
+pub fn config_batch(cfg: &mut web::ServiceConfig) {
+    cfg.route("/batch", web::post().to(apply_batch));
+}
+
+///Whether user may apply operation to res, and whether it would change anything
+fn check_item(
+    res: &Resource<SeaweedFsId>,
+    user: &User,
+    operation: &BatchOperation,
+    tag: Option<&str>,
+    grantee: Option<&ObjectId>,
+) -> BatchStatus {
+    let allowed = match operation {
+        BatchOperation::Delete => res.can_delete(Some(user)),
+        BatchOperation::AddTag { .. } => res.can_write(Some(user)),
+        BatchOperation::SetPublic { .. } | BatchOperation::GrantAccess { .. } => {
+            res.can_share(Some(user))
+        }
+    };
+    if !allowed {
+        return BatchStatus::Forbidden;
+    }
+    let tags = res.get_tags();
+    match (tag, grantee) {
+        (Some(tag), _) if tags.len() >= TAGS_MAX && !tags.iter().any(|t| t == tag) => {
+            BatchStatus::Skipped
+        }
+        (_, Some(grantee)) if *grantee == res.get_owner() => BatchStatus::Skipped,
+        _ => BatchStatus::Ok,
+    }
+}
+
+///Update statements applying operation to ids
+fn update_statements(
+    ids: &[ObjectId],
+    operation: &BatchOperation,
+    tag: Option<String>,
+    grantee: Option<ObjectId>,
+) -> Vec<Document> {
+    match operation {
+        BatchOperation::Delete => vec![doc! {
+            "q": {"_id": {"$in": ids}, "deleted_at": Bson::Null},
+            "u": {"$set": {"deleted_at": Bson::DateTime(Utc::now())}},
+            "multi": true
+        }],
+        BatchOperation::AddTag { .. } => vec![doc! {
+            "q": {"_id": {"$in": ids}},
+            "u": {"$addToSet": {"tags": tag.unwrap()}},
+            "multi": true
+        }],
+        BatchOperation::SetPublic { read, write } => {
+            let mut set = Document::new();
+            if let Some(read) = read {
+                set.insert("r_public", *read);
+            }
+            if let Some(write) = write {
+                set.insert("w_public", *write);
+            }
+            vec![doc! {"q": {"_id": {"$in": ids}}, "u": {"$set": set}, "multi": true}]
+        }
+        BatchOperation::GrantAccess { write, .. } => {
+            let grantee = grantee.unwrap();
+            vec![
+                doc! {
+                    "q": {"_id": {"$in": ids}, "access.user": {"$ne": &grantee}},
+                    "u": {"$push": {"access": {"user": &grantee, "write": *write}}},
+                    "multi": true
+                },
+                doc! {
+                    "q": {"_id": {"$in": ids}, "access.user": &grantee},
+                    "u": {"$set": {"access.$[grantee].write": *write}},
+                    "arrayFilters": [{"grantee.user": &grantee}],
+                    "multi": true
+                },
+            ]
+        }
+    }
+}
+
+///Apply one operation to many resources, reporting the outcome of each of them
+pub async fn apply_batch(user: User, req: web::Json<BatchReq>) -> ResourceResponse {
+    if req.ids.len() > MAX_BATCH_SIZE {
+        return Err(ResourceIOError::InvalidQuery(format!(
+            "at most {} resources can be modified at once",
+            MAX_BATCH_SIZE
+        )));
+    }
+    let db = get_mongo().await;
+    let tag = match &req.operation {
+        BatchOperation::AddTag { tag } => Some(
+            normalize_tag(tag)?
+                .ok_or_else(|| ResourceIOError::InvalidQuery("tag is empty".to_string()))?,
+        ),
+        _ => None,
+    };
+    let grantee = match &req.operation {
+        BatchOperation::GrantAccess { username, .. } => Some(
+            db.get_user_by_name(username)
+                .await?
+                .and_then(|u| u.get_id())
+                .ok_or_else(|| ResourceIOError::InvalidQuery("unknown user".to_string()))?,
+        ),
+        _ => None,
+    };
+    if let BatchOperation::SetPublic {
+        read: None,
+        write: None,
+    } = req.operation
+    {
+        return Err(ResourceIOError::InvalidQuery(
+            "no public flag to change".to_string(),
+        ));
+    }
+
+    let ids: Vec<ObjectId> = req
+        .ids
+        .iter()
+        .filter_map(|id| ObjectId::with_string(id).ok())
+        .collect();
+    let found: HashMap<ObjectId, Resource<SeaweedFsId>> = db
+        .find_resources::<SeaweedFsId>(&ids)
+        .await?
+        .into_iter()
+        .filter(|r| !r.is_trashed())
+        .map(|r| (r.get_id().unwrap().clone(), r))
+        .collect();
+
+    let items: Vec<BatchItemResult> = req
+        .ids
+        .iter()
+        .map(|id| {
+            let status = match ObjectId::with_string(id).ok().and_then(|id| found.get(&id)) {
+                Some(res) => {
+                    check_item(res, &user, &req.operation, tag.as_deref(), grantee.as_ref())
+                }
+                None => BatchStatus::NotFound,
+            };
+            BatchItemResult::new(id, status)
+        })
+        .collect();
+    let allowed: Vec<ObjectId> = items
+        .iter()
+        .filter(|i| i.status == BatchStatus::Ok)
+        .filter_map(|i| i.object_id())
+        .collect();
+
+    let modified = if allowed.is_empty() {
+        0
+    } else {
+        db.bulk_update_resources(update_statements(&allowed, &req.operation, tag, grantee))
+            .await?
+    };
+    Ok(HttpResponse::Ok().json(BatchReport { modified, items }))
+}
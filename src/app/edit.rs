@@ -0,0 +1,152 @@
+use super::media::discard;
+use super::watermark::watermarked;
+use crate::{
+    config::Config,
+    db::get_mongo,
+    models::{EditOperation, EditReq, Media, Readable, Resource, User, Writable},
+    tools::{render_image, ResourceIOError, ResponseStream, SeaweedFsId},
+};
+use actix_web::{web, HttpResponse};
+use futures::StreamExt;
+use mongodb::bson::oid::ObjectId;
+use std::io;
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+pub fn config_edit(cfg: &mut web::ServiceConfig) {
+    cfg.route("{id}/edits", web::put().to(set_edits))
+        .route("{id}/rendered", web::get().to(get_rendered))
+        .route("{id}/versions", web::get().to(get_versions))
+        .route(
+            "{id}/versions/{number}/revert",
+            web::post().to(revert_version),
+        );
+}
+
+async fn find_media(id: &str) -> Result<Resource<SeaweedFsId>, ResourceIOError> {
+    let id = ObjectId::with_string(id).map_err(|_| ResourceIOError::NotFound)?;
+    get_mongo()
+        .await
+        .find_resource(&id)
+        .await?
+        .filter(|r| !r.is_trashed())
+        .ok_or(ResourceIOError::NotFound)
+}
+
+///Resource user can edit, only images are
+async fn find_editable(id: &str, user: &User) -> Result<Resource<SeaweedFsId>, ResourceIOError> {
+    let res = find_media(id).await?;
+    if !res.can_write(Some(user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "writing".to_string(),
+        ));
+    }
+    if res.get_extension().type_() != mime::IMAGE {
+        return Err(ResourceIOError::UnsupportedMediaType("image/*".to_string()));
+    }
+    Ok(res)
+}
+
+///Record operations as the current edits, rendering them upright once so that displays are cheap
+async fn save_edits(
+    mut res: Resource<SeaweedFsId>,
+    operations: Vec<EditOperation>,
+    user: &User,
+) -> Result<Resource<SeaweedFsId>, ResourceIOError> {
+    res.set_edits(operations, user.get_id().unwrap());
+    let rendered = if res.needs_rendering() {
+        let mut stream = res.read(Some(user)).await?;
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk.map_err(io::Error::other)?);
+        }
+        let orientation = res.get_orientation();
+        let edits = res.get_edits().to_vec();
+        let jpeg = res.rendered_mime() == mime::IMAGE_JPEG;
+        let rendered =
+            tokio::task::spawn_blocking(move || render_image(&data, orientation, &edits, jpeg))
+                .await
+                .map_err(io::Error::other)??;
+        let storage = SeaweedFsId::alloc().await;
+        storage.save(rendered).await;
+        Some(storage)
+    } else {
+        None
+    };
+
+    let previous = res.replace_rendered(rendered);
+    if !get_mongo().await.update_edits(&res).await? {
+        //The other edit keeps its rendering, this one is dropped
+        if let Some(rendered) = res.get_rendered() {
+            rendered.delete().await?;
+        }
+        return Err(ResourceIOError::EditConflict);
+    }
+    if let Some(previous) = previous {
+        discard(&previous).await;
+    }
+    Ok(res)
+}
+
+///Replace every edit of an image, an empty list shows the original upright again
+pub async fn set_edits(
+    path: web::Path<String>,
+    user: User,
+    req: web::Json<EditReq>,
+) -> ResourceResponse {
+    req.validate()?;
+    let res = find_editable(&path, &user).await?;
+    let res = save_edits(res, req.into_inner().operations, &user).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+///Edits of an image as they were in a previous version, recorded as a new version
+pub async fn revert_version(path: web::Path<(String, u32)>, user: User) -> ResourceResponse {
+    let (id, number) = path.into_inner();
+    let res = find_editable(&id, &user).await?;
+    let operations = res
+        .find_version(number)
+        .map(|v| v.operations.clone())
+        .ok_or(ResourceIOError::NotFound)?;
+    let res = save_edits(res, operations, &user).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+///History of edits, oldest first
+pub async fn get_versions(path: web::Path<String>, user: User) -> ResourceResponse {
+    let res = find_media(&path).await?;
+    if !res.can_read(Some(&user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "reading".to_string(),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(res.get_versions()))
+}
+
+///Media turned upright with its edits applied
+pub async fn get_rendered(
+    path: web::Path<String>,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    let mut res = find_media(&path).await?;
+    if !res.can_read(Some(&user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "reading".to_string(),
+        ));
+    }
+    if let Some(storage) = watermarked(&mut res, &user, &config).await? {
+        return Ok(HttpResponse::Ok()
+            .content_type(res.rendered_mime().essence_str())
+            .streaming(ResponseStream {
+                stream: storage.read().await,
+            }));
+    }
+    let (stream, content_type) = match res.get_rendered() {
+        Some(rendered) => (rendered.read().await, res.rendered_mime()),
+        None => (res.read(Some(&user)).await?, res.get_extension().clone()),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type.essence_str())
+        .streaming(ResponseStream { stream }))
+}
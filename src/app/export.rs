@@ -0,0 +1,269 @@
+use super::archive::{name_entries, stream_archive};
+use super::media::discard;
+use crate::{
+    config::Config,
+    db::{get_mongo, is_duplicate_key},
+    models::{
+        Export, ExportStatus, Media, Readable, Resource, User, UserInfo, Writable,
+        EXPORT_TIMEOUT_HOURS,
+    },
+    tools::{Mail, Mailer, ResourceIOError, ResponseStream, SeaweedFsId, ZIP_MAX_ENTRY_SIZE},
+};
+use actix_web::{http::header, web, HttpResponse};
+use bytes::Bytes;
+use chrono::{Duration, Utc};
+use log::{error, warn};
+use mongodb::bson::{oid::ObjectId, to_bson, to_document, Bson};
+use serde_json::json;
+use std::{io, path::Path};
+use tokio::{fs, io::AsyncWriteExt};
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+pub fn config_export(cfg: &mut web::ServiceConfig) {
+    cfg.route("/exports", web::post().to(start_export))
+        .route("/exports", web::get().to(get_exports))
+        .route("/exports/{id}", web::get().to(download_export));
+}
+
+///Fields of res worth exporting, along with the name of its file in the archive
+fn media_json(res: &Resource<SeaweedFsId>, file: Option<&str>) -> io::Result<serde_json::Value> {
+    let mut doc = to_document(res).map_err(io::Error::other)?;
+    //Storage location is internal to this instance
+    for key in ["_storage", "poster", "preview", "rendered", "watermarked"] {
+        doc.remove(key);
+    }
+    if let Some(file) = file {
+        doc.insert("file", file);
+    }
+    Ok(Bson::Document(doc).into_relaxed_extjson())
+}
+
+///JSON manifest describing every archived media and their owner,
+///along with media too large to be archived
+fn manifest(
+    user: &User,
+    entries: &[(String, Resource<SeaweedFsId>)],
+    skipped: &[Resource<SeaweedFsId>],
+) -> io::Result<Bytes> {
+    let media = entries
+        .iter()
+        .map(|(name, res)| media_json(res, Some(name)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let skipped = skipped
+        .iter()
+        .map(|res| media_json(res, None))
+        .collect::<io::Result<Vec<_>>>()?;
+    let user = to_bson(&UserInfo::from(user))
+        .map_err(io::Error::other)?
+        .into_relaxed_extjson();
+    let manifest = json!({
+        "exportedAt": Utc::now().to_rfc3339(),
+        "user": user,
+        "media": media,
+        "skipped": skipped,
+    });
+    Ok(serde_json::to_vec_pretty(&manifest)?.into())
+}
+
+///Build the archive of user and store it, returns its storage, size and number of media.
+///The archive is staged on disk as storage only accepts whole files
+async fn build_export(
+    id: &ObjectId,
+    user: &User,
+    config: &Config,
+) -> Result<(SeaweedFsId, i64, i64), ResourceIOError> {
+    let db = get_mongo().await;
+    //Entries cannot hold ZIP_MAX_ENTRY_SIZE bytes, larger media are only listed
+    let (resources, skipped): (Vec<_>, Vec<_>) = db
+        .find_all_owned_resources::<SeaweedFsId>(&user.get_id().unwrap())
+        .await?
+        .into_iter()
+        .filter(|r| !r.is_trashed())
+        .partition(|r| (r.get_size() as u64) < ZIP_MAX_ENTRY_SIZE);
+    let entries = name_entries(resources);
+    let count = entries.len() as i64;
+    let extra = vec![(
+        "manifest.json".to_string(),
+        manifest(user, &entries, &skipped)?,
+    )];
+
+    fs::create_dir_all(&config.upload_dir).await?;
+    let path = config.upload_dir.join(format!("export-{}", id.to_hex()));
+    let staged = stage_archive(&path, entries, extra, user, config).await;
+    let stored = match staged {
+        Ok(size) => {
+            let storage = SeaweedFsId::alloc().await;
+            storage.save_file(&path).await.map(|()| (storage, size))
+        }
+        Err(e) => Err(e),
+    };
+    fs::remove_file(&path).await?;
+    let (storage, size) = stored?;
+    Ok((storage, size, count))
+}
+
+///Write the archive of entries and extra to a file at path, returns its size
+async fn stage_archive(
+    path: &Path,
+    entries: Vec<(String, Resource<SeaweedFsId>)>,
+    extra: Vec<(String, Bytes)>,
+    user: &User,
+    config: &Config,
+) -> io::Result<i64> {
+    let mut file = fs::File::create(path).await?;
+    let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+    let writer = async move {
+        let result = stream_archive(entries, extra, user, config, &tx).await;
+        drop(tx);
+        result
+    };
+    let collector = async {
+        let mut size = 0i64;
+        while let Some(chunk) = rx.recv().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            size += chunk.len() as i64;
+        }
+        file.flush().await?;
+        Ok::<_, io::Error>(size)
+    };
+    let (written, size) = futures::join!(writer, collector);
+    written?;
+    size
+}
+
+///Build export id in the background, then replace older exports and notify user
+async fn run_export(
+    id: ObjectId,
+    user: User,
+    config: web::Data<Config>,
+    mailer: web::Data<dyn Mailer>,
+) {
+    let db = get_mongo().await;
+    let (storage, size, count) = match build_export(&id, &user, &config).await {
+        Ok(built) => built,
+        Err(e) => {
+            error!("Export {} failed: {}", id.to_hex(), e);
+            if let Err(e) = db.fail_export(&id).await {
+                error!("Cannot mark export {} failed: {}", id.to_hex(), e);
+            }
+            return;
+        }
+    };
+    match db.finish_export(&id, &storage, size, count).await {
+        Ok(true) => {}
+        //The account was deleted meanwhile
+        Ok(false) => {
+            discard(&storage).await;
+            return;
+        }
+        Err(e) => {
+            error!("Export {} failed: {}", id.to_hex(), e);
+            discard(&storage).await;
+            return;
+        }
+    }
+
+    if let Ok(exports) = db
+        .find_exports_by_owner::<SeaweedFsId>(&user.get_id().unwrap())
+        .await
+    {
+        for old in exports.iter().filter(|e| e.get_id() != Some(&id)) {
+            if let Some(storage) = old.get_storage() {
+                discard(storage).await;
+            }
+            let _ = db.delete_export(old.get_id().unwrap()).await;
+        }
+    }
+
+    if let Some(email) = user.email.clone() {
+        let sent = mailer
+            .send(Mail {
+                to: email,
+                subject: "Your Pixure export is ready".to_string(),
+                body: format!(
+                    "The export of your account {} is ready.\n\
+                    Download it while logged in from:\n\
+                    {}/user/exports/{}",
+                    user.get_username(),
+                    config.public_url,
+                    id.to_hex()
+                ),
+            })
+            .await;
+        if let Err(e) = sent {
+            warn!("Cannot notify export {}: {}", id.to_hex(), e);
+        }
+    }
+}
+
+///Delete every export of user along with its archive, which holds copies of their media
+pub async fn delete_exports(user_id: &ObjectId) -> Result<(), ResourceIOError> {
+    let db = get_mongo().await;
+    for export in db.find_exports_by_owner::<SeaweedFsId>(user_id).await? {
+        db.delete_export(export.get_id().unwrap()).await?;
+        if let Some(storage) = export.get_storage() {
+            discard(storage).await;
+        }
+    }
+    Ok(())
+}
+
+///Start exporting every media of the user, one export at a time
+pub async fn start_export(
+    user: User,
+    config: web::Data<Config>,
+    mailer: web::Data<dyn Mailer>,
+) -> ResourceResponse {
+    let db = get_mongo().await;
+    let user_id = user.get_id().unwrap();
+    db.fail_stale_exports(&user_id, Utc::now() - Duration::hours(EXPORT_TIMEOUT_HOURS))
+        .await?;
+    //Only one export per user can be pending, see db_setup
+    let id = match db.save_export(Export::<SeaweedFsId>::new(&user)).await {
+        Ok(id) => id,
+        Err(e) if is_duplicate_key(&e) => return Err(ResourceIOError::ExportInProgress),
+        Err(e) => return Err(e.into()),
+    };
+    let info = db
+        .find_export::<SeaweedFsId>(&id)
+        .await?
+        .ok_or(ResourceIOError::NotFound)?
+        .info(&config.public_url);
+    actix_web::rt::spawn(run_export(id, user, config.clone(), mailer.clone()));
+    Ok(HttpResponse::Accepted().json(info))
+}
+
+pub async fn get_exports(user: User, config: web::Data<Config>) -> ResourceResponse {
+    let db = get_mongo().await;
+    let exports: Vec<_> = db
+        .find_exports_by_owner::<SeaweedFsId>(&user.get_id().unwrap())
+        .await?
+        .iter()
+        .map(|e| e.info(&config.public_url))
+        .collect();
+    Ok(HttpResponse::Ok().json(exports))
+}
+
+pub async fn download_export(path: web::Path<String>, user: User) -> ResourceResponse {
+    let id = ObjectId::with_string(&path).map_err(|_| ResourceIOError::NotFound)?;
+    let db = get_mongo().await;
+    let export = db
+        .find_export::<SeaweedFsId>(&id)
+        .await?
+        .filter(|e| Some(e.get_owner()) == user.get_id().as_ref())
+        .filter(|e| e.get_status() == ExportStatus::Ready)
+        .ok_or(ResourceIOError::NotFound)?;
+    let stream = export.get_storage().unwrap().read().await;
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"pixure-export-{}.zip\"",
+                user.get_username()
+            ),
+        ))
+        .streaming(ResponseStream { stream }))
+}
@@ -0,0 +1,83 @@
+use crate::{
+    db::{get_mongo, PaginationOptions},
+    models::{BoundingBox, ClusterQuery, GeoPoint, RadiusQuery, User, MAX_ZOOM},
+    tools::{ResourceIOError, SeaweedFsId},
+};
+use actix_web::{web, HttpResponse};
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+///Largest radius accepted, about half the circumference of the earth
+const MAX_RADIUS: f64 = 20_000_000.0;
+
+pub fn config_geo(cfg: &mut web::ServiceConfig) {
+    cfg.route("/geo/within", web::get().to(get_media_within))
+        .route("/geo/near", web::get().to(get_media_near))
+        .route("/geo/clusters", web::get().to(get_clusters));
+}
+
+fn invalid_bbox() -> ResourceIOError {
+    ResourceIOError::InvalidQuery("bounding box is out of range".to_string())
+}
+
+pub async fn get_media_within(
+    user: User,
+    bbox: web::Query<BoundingBox>,
+    pagination: web::Query<PaginationOptions>,
+) -> ResourceResponse {
+    if !bbox.is_valid() {
+        return Err(invalid_bbox());
+    }
+    let db = get_mongo().await;
+    let found = db
+        .find_resources_within::<SeaweedFsId>(&user.get_id().unwrap(), &bbox, &pagination)
+        .await?;
+    Ok(HttpResponse::Ok().json(found))
+}
+
+pub async fn get_media_near(
+    user: User,
+    query: web::Query<RadiusQuery>,
+    pagination: web::Query<PaginationOptions>,
+) -> ResourceResponse {
+    let center = GeoPoint::new(query.lng, query.lat)
+        .ok_or_else(|| ResourceIOError::InvalidQuery("center is out of range".to_string()))?;
+    if !(query.radius > 0.0 && query.radius <= MAX_RADIUS) {
+        return Err(ResourceIOError::InvalidQuery(format!(
+            "radius must be between 0 and {} meters",
+            MAX_RADIUS
+        )));
+    }
+    let db = get_mongo().await;
+    let found = db
+        .find_resources_near::<SeaweedFsId>(
+            &user.get_id().unwrap(),
+            &center,
+            query.radius,
+            &pagination,
+        )
+        .await?;
+    Ok(HttpResponse::Ok().json(found))
+}
+
+///Media of bbox grouped per map tile at the requested zoom level
+pub async fn get_clusters(
+    user: User,
+    bbox: web::Query<BoundingBox>,
+    query: web::Query<ClusterQuery>,
+) -> ResourceResponse {
+    if !bbox.is_valid() {
+        return Err(invalid_bbox());
+    }
+    if query.zoom > MAX_ZOOM {
+        return Err(ResourceIOError::InvalidQuery(format!(
+            "zoom cannot exceed {}",
+            MAX_ZOOM
+        )));
+    }
+    let db = get_mongo().await;
+    let clusters = db
+        .cluster_resources(&user.get_id().unwrap(), &bbox, query.zoom)
+        .await?;
+    Ok(HttpResponse::Ok().json(clusters))
+}
@@ -1,58 +1,716 @@
-use crate::models::{Media, Resource, User};
-use crate::tools::{ResponseStream, SeaweedFsId};
+use super::animation::config_animation;
+use super::archive::config_archive;
+use super::batch::config_batch;
+use super::edit::config_edit;
+use super::geo::config_geo;
+use super::takeout::config_takeout;
+use super::timeline::config_timeline;
+use super::trash::config_trash;
+use super::tus::config_tus;
+use super::watermark::watermarked;
+use crate::config::Config;
+use crate::db::{is_duplicate_key, PaginationOptions};
+use crate::models::{
+    Blob, BytesStream, Identifiable, Media, MediaUpdateReq, Readable, Resource, User, Writable,
+};
+use crate::tools::{
+    animated_preview, camera_info, dhash, encode_image, exif_orientation, fetch_remote,
+    gps_location, group_similar, hamming_distance, orient, placeholder, poster_frame,
+    probe_animation, probe_video, read_exif, staged_poster_frame, video_head, ResponseStream,
+    SeaweedFsId,
+};
 use crate::{db::get_mongo, tools::ResourceIOError};
 use actix_multipart::Multipart;
-use actix_web::{web, HttpResponse};
-use futures::{StreamExt, TryStreamExt};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use futures::{Future, StreamExt, TryStreamExt};
+use log::warn;
 use mongodb::bson::oid::ObjectId;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::{io, path::Path};
+use tokio::{fs, io::AsyncReadExt};
 
 type ResourceResponse = Result<HttpResponse, ResourceIOError>;
 
+///Staged uploads up to this size are read in memory to extract their metadata
+const IN_MEMORY_MAX_SIZE: i64 = 256 * 1024 * 1024;
+
 pub fn config_media(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/media")
+            .configure(config_tus)
+            .configure(config_trash)
+            .configure(config_geo)
+            .configure(config_timeline)
+            .configure(config_batch)
+            .configure(config_archive)
+            .configure(config_takeout)
+            .configure(config_edit)
+            .configure(config_animation)
             .route("/upload", web::post().to(add_media))
-            .route("{id}", web::get().to(get_media)),
+            .route("/import", web::post().to(import_media))
+            .route("/duplicates", web::get().to(get_duplicates))
+            .route("/search", web::get().to(search_media))
+            .route("{id}/similar", web::get().to(get_similar_media))
+            .route("{id}/poster", web::get().to(get_poster))
+            .route("{id}", web::get().to(get_media))
+            .route("{id}", web::patch().to(update_media)),
     );
 }
 
-pub async fn add_media(mut payload: Multipart, user: User) -> ResourceResponse {
-    let db = get_mongo().await;
-    //TODO sanitize input
+pub async fn add_media(
+    req: HttpRequest,
+    mut payload: Multipart,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    //Refuse uploads that cannot fit before reading them
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<i64>().ok())
+        .unwrap_or(0);
+    check_quota(&user, length, config.storage_quota).await?;
+    let mut uploaded = Vec::new();
     while let Ok(Some(mut field)) = payload.try_next().await {
-        let mut res = Resource::<SeaweedFsId>::from_field(&field, &user);
-        res.alloc().await;
+        let res = Resource::<SeaweedFsId>::from_field(&field, &user);
         //res.update_public_access(Some(true), Some(true));
 
         let mut file_data = Vec::new();
         while let Some(chunk) = field.next().await {
             file_data.append(&mut chunk.unwrap().to_vec());
         }
-        println!("Saving file");
-        res.save(Some(&user), file_data).await?;
+        uploaded.push(store_resource(res, &user, file_data, &config).await?);
+    }
+
+    Ok(HttpResponse::Ok().json(uploaded))
+}
+
+#[derive(Deserialize)]
+pub struct ImportReq {
+    url: String,
+}
 
-        db.save_resource(res).await?;
+///Fetch a remote image or video on behalf of the user
+pub async fn import_media(
+    req: web::Json<ImportReq>,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    check_quota(&user, 0, config.storage_quota).await?;
+    let remote = fetch_remote(&req.url, &config).await?;
+    let mut res = Resource::<SeaweedFsId>::new(remote.mime, &user);
+    if let Some(filename) = &remote.filename {
+        res.set_filename(filename);
     }
+    let uploaded = store_resource(res, &user, remote.data, &config).await?;
+    Ok(HttpResponse::Ok().json(uploaded))
+}
 
-    Ok(HttpResponse::Ok().finish())
+#[derive(Serialize)]
+pub struct UploadResult {
+    pub id: ObjectId,
+    pub sha256: String,
+    ///Same bytes were already stored by the same user
+    pub duplicate: bool,
 }
 
-pub async fn get_media(path: web::Path<String>, user: User) -> ResourceResponse {
-    let id = path.into_inner();
+///Fail if user cannot store bytes more without exceeding quota
+pub async fn check_quota(
+    user: &User,
+    bytes: i64,
+    quota: Option<i64>,
+) -> Result<(), ResourceIOError> {
+    if let Some(quota) = quota {
+        let used = get_mongo()
+            .await
+            .get_user_by_id(&user.get_id().unwrap())
+            .await?
+            .map(|u| u.storage_used)
+            .unwrap_or(0);
+        if used + bytes > quota {
+            return Err(ResourceIOError::QuotaExceeded);
+        }
+    }
+    Ok(())
+}
+
+///Save a new resource holding data, accounting it against the owner quota.
+///Data already stored is not written again, the existing storage is referenced instead
+pub async fn store_resource(
+    res: Resource<SeaweedFsId>,
+    user: &User,
+    data: Vec<u8>,
+    config: &Config,
+) -> Result<UploadResult, ResourceIOError> {
+    let size = data.len() as i64;
+    reserved(user, size, config, store_data(res, user, data, config)).await
+}
+
+///Account size bytes against the quota of user while store runs, they are released if it fails
+async fn reserved(
+    user: &User,
+    size: i64,
+    config: &Config,
+    store: impl Future<Output = Result<UploadResult, ResourceIOError>>,
+) -> Result<UploadResult, ResourceIOError> {
     let db = get_mongo().await;
+    let user_id = user.get_id().unwrap();
+    if !db
+        .reserve_storage(&user_id, size, config.storage_quota)
+        .await?
+    {
+        return Err(ResourceIOError::QuotaExceeded);
+    }
+    let result = store.await;
+    if result.is_err() {
+        db.add_storage_used(&user_id, -size).await?;
+    }
+    result
+}
 
-    let doc: Resource<SeaweedFsId> = db
-        .find_resource(&ObjectId::with_string(&id).unwrap())
+async fn store_data(
+    mut res: Resource<SeaweedFsId>,
+    user: &User,
+    data: Vec<u8>,
+    config: &Config,
+) -> Result<UploadResult, ResourceIOError> {
+    let db = get_mongo().await;
+    let size = data.len() as i64;
+    let hash = hex::encode(digest::digest(&digest::SHA256, &data));
+    res.set_size(size);
+    res.set_hash(hash.clone());
+    let data = if res.get_extension().type_() == mime::IMAGE {
+        let jpeg = res.rendered_mime() == mime::IMAGE_JPEG;
+        //Decoding is CPU bound, keep it away from the async workers
+        let (data, phash, preview, camera, location, orientation, upright) =
+            tokio::task::spawn_blocking(move || {
+                let exif = read_exif(&data);
+                let camera = exif.as_ref().and_then(camera_info);
+                let location = exif.as_ref().and_then(gps_location);
+                let orientation = exif.as_ref().and_then(exif_orientation).unwrap_or(1);
+                let img = image::load_from_memory(&data)
+                    .ok()
+                    .map(|img| orient(img, orientation));
+                //Hashed upright so that copies differing by their orientation tag match
+                let phash = img.as_ref().map(dhash);
+                let preview = img.as_ref().map(placeholder);
+                let upright = img
+                    .filter(|_| orientation != 1)
+                    .and_then(|img| encode_image(&img, jpeg).ok());
+                (data, phash, preview, camera, location, orientation, upright)
+            })
+            .await
+            .unwrap();
+        if let Some(phash) = phash {
+            res.set_phash(phash);
+        }
+        if let Some((blurhash, palette)) = preview {
+            res.set_placeholder(blurhash, palette);
+        }
+        res.set_orientation(orientation);
+        //The original is kept as is, an upright copy is displayed instead
+        if let Some(upright) = upright {
+            let storage = SeaweedFsId::alloc().await;
+            storage.save(upright).await;
+            res.replace_rendered(Some(storage));
+        }
+        if let Some(camera) = camera {
+            res.set_exif(camera);
+        }
+        //A location given along the file takes precedence
+        if let Some(location) = location.filter(|_| res.get_location().is_none()) {
+            res.set_location(location);
+        }
+        //Animations are shown as a still thumbnail of their first frame until they are played
+        let (data, animation) = tokio::task::spawn_blocking(move || {
+            let animation = probe_animation(&data).and_then(|(info, thumbnail)| {
+                let thumbnail = encode_image(&thumbnail, true).ok()?;
+                Some((info, thumbnail, animated_preview(&data)))
+            });
+            (data, animation)
+        })
         .await
-        .unwrap()
         .unwrap();
+        if let Some((info, thumbnail, preview)) = animation {
+            let storage = SeaweedFsId::alloc().await;
+            storage.save(thumbnail).await;
+            res.set_poster(storage);
+            if let Some(preview) = preview {
+                let storage = SeaweedFsId::alloc().await;
+                storage.save(preview).await;
+                res.set_preview(storage);
+            }
+            res.set_animation(info);
+        }
+        data
+    } else {
+        //Only videos are probed, clients may not know their type
+        if let Some(video) = probe_video(&data).filter(|v| v.codec.is_some()) {
+            if let Some(ffmpeg) = &config.ffmpeg {
+                if let Some(poster) = poster_frame(ffmpeg, &config.upload_dir, &data, &video).await
+                {
+                    set_video_poster(&mut res, poster).await;
+                }
+            }
+            res.set_video(video);
+        }
+        data
+    };
 
-    let stream = doc.read(Some(&user)).await;
+    let duplicate = db.has_owned_hash(&res.get_owner(), &hash).await?;
+    store_blob(&mut res, user, &hash, BlobData::Memory(data), size).await?;
+    let id = db.save_resource(res).await?;
+    Ok(UploadResult {
+        id,
+        sha256: hash,
+        duplicate,
+    })
+}
 
-    match stream {
-        Ok(s) => Ok(HttpResponse::Ok()
-            .content_type(doc.get_extension().essence_str())
-            .streaming(ResponseStream { stream: s })),
-        Err(_) => Ok(HttpResponse::Unauthorized().finish()),
+///Save a new resource holding the file staged at path, like store_resource.
+///Files too large to be held in memory are streamed to storage and only probed as videos
+pub async fn store_staged(
+    res: Resource<SeaweedFsId>,
+    user: &User,
+    path: &Path,
+    config: &Config,
+) -> Result<UploadResult, ResourceIOError> {
+    let size = fs::metadata(path).await?.len() as i64;
+    if size <= IN_MEMORY_MAX_SIZE {
+        return store_resource(res, user, fs::read(path).await?, config).await;
     }
+    reserved(
+        user,
+        size,
+        config,
+        store_file(res, user, path, size, config),
+    )
+    .await
+}
+
+async fn store_file(
+    mut res: Resource<SeaweedFsId>,
+    user: &User,
+    path: &Path,
+    size: i64,
+    config: &Config,
+) -> Result<UploadResult, ResourceIOError> {
+    let db = get_mongo().await;
+    let hash = hash_file(path).await?;
+    res.set_size(size);
+    res.set_hash(hash.clone());
+    let head = video_head(path).await?;
+    if let Some(video) = probe_video(&head).filter(|v| v.codec.is_some()) {
+        if let Some(ffmpeg) = &config.ffmpeg {
+            if let Some(poster) = staged_poster_frame(ffmpeg, path, &video).await {
+                set_video_poster(&mut res, poster).await;
+            }
+        }
+        res.set_video(video);
+    }
+
+    let duplicate = db.has_owned_hash(&res.get_owner(), &hash).await?;
+    store_blob(&mut res, user, &hash, BlobData::Staged(path), size).await?;
+    let id = db.save_resource(res).await?;
+    Ok(UploadResult {
+        id,
+        sha256: hash,
+        duplicate,
+    })
+}
+
+///Hex SHA-256 of the file at path, read chunk by chunk
+async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+    Ok(hex::encode(context.finish()))
+}
+
+///Store poster as the still shown for a video, along with its placeholder
+async fn set_video_poster(res: &mut Resource<SeaweedFsId>, poster: Vec<u8>) {
+    let (poster, preview) = tokio::task::spawn_blocking(move || {
+        let preview = image::load_from_memory(&poster)
+            .ok()
+            .map(|img| placeholder(&img));
+        (poster, preview)
+    })
+    .await
+    .unwrap();
+    if let Some((blurhash, palette)) = preview {
+        res.set_placeholder(blurhash, palette);
+    }
+    let storage = SeaweedFsId::alloc().await;
+    storage.save(poster).await;
+    res.set_poster(storage);
+}
+
+///Bytes written by store_blob
+pub enum BlobData<'a> {
+    Memory(Vec<u8>),
+    ///File staged on disk, streamed to storage
+    Staged(&'a Path),
+}
+
+///Point res, which has no storage yet, to one holding data.
+///Data is written unless the same bytes are already stored, by anyone
+pub async fn store_blob(
+    res: &mut Resource<SeaweedFsId>,
+    user: &User,
+    hash: &str,
+    data: BlobData<'_>,
+    size: i64,
+) -> Result<(), ResourceIOError> {
+    let db = get_mongo().await;
+    if let Some(blob) = db.acquire_blob::<SeaweedFsId>(hash).await? {
+        res.set_storage(blob.get_storage().clone());
+        return Ok(());
+    }
+    res.alloc().await;
+    match data {
+        BlobData::Memory(data) => res.save(Some(user), data).await?,
+        BlobData::Staged(path) => res.save_file(Some(user), path).await?,
+    }
+    let storage = res.get_storage().clone().unwrap();
+    match db
+        .save_blob(Blob::new(hash.to_string(), storage, size))
+        .await
+    {
+        Ok(()) => Ok(()),
+        //Same bytes were stored concurrently, keep the other copy
+        Err(e) if is_duplicate_key(&e) => {
+            res.delete(Some(user)).await?;
+            let blob = db
+                .acquire_blob::<SeaweedFsId>(hash)
+                .await?
+                .ok_or(ResourceIOError::NotFound)?;
+            res.set_storage(blob.get_storage().clone());
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+///Whole data of a stream
+pub async fn read_all(mut stream: BytesStream) -> Result<Vec<u8>, ResourceIOError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk.map_err(io::Error::other)?);
+    }
+    Ok(data)
+}
+
+///Remove a resource along with its accounted size, only the owner or an admin can do it
+pub async fn remove_resource(
+    res: &Resource<SeaweedFsId>,
+    user: &User,
+) -> Result<(), ResourceIOError> {
+    if !res.can_delete(Some(user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "deleting".to_string(),
+        ));
+    }
+    purge_resource(res).await
+}
+
+///Remove a resource without checking permissions.
+///Its storage is deleted once no other resource references it
+pub async fn purge_resource(res: &Resource<SeaweedFsId>) -> Result<(), ResourceIOError> {
+    let db = get_mongo().await;
+    //The document goes first so that the blob is released once, even when purges overlap
+    if !db.delete_resource(res.get_id().unwrap()).await? {
+        return Ok(());
+    }
+    db.add_storage_used(&res.get_owner(), -res.get_size())
+        .await?;
+    let unused = match res.get_hash() {
+        Some(hash) => db.release_blob::<SeaweedFsId>(hash).await?,
+        None => true,
+    };
+    if unused {
+        if let Some(storage) = res.get_storage() {
+            discard(storage).await;
+        }
+    }
+    let watermarked = res.get_watermarked().map(|w| &w.storage);
+    for derivative in res
+        .get_poster()
+        .iter()
+        .chain(res.get_preview().iter())
+        .chain(res.get_rendered().iter())
+        .chain(watermarked.iter())
+    {
+        discard(derivative).await;
+    }
+    Ok(())
+}
+
+///Delete storage nothing references anymore, a failure only leaves an orphan file behind
+pub async fn discard(storage: &SeaweedFsId) {
+    if let Err(e) = storage.delete().await {
+        warn!("Cannot delete {}: {}", storage.get_uid(), e);
+    }
+}
+
+///First range of a Range header within size, as inclusive bounds.
+///None when the header is ignored, Some(Err) when the range cannot be satisfied
+fn parse_range(value: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    //Several ranges are answered with the whole content
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let len: u64 = suffix.parse().ok()?;
+            if len == 0 {
+                return Some(Err(()));
+            }
+            (size.saturating_sub(len), size - 1)
+        }
+        (start, "") => (start.parse().ok()?, size - 1),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.min(size - 1))
+        }
+    };
+    if start >= size {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
+
+///Stream a resource, answering Range requests so that videos can be seeked
+pub async fn get_media(
+    req: HttpRequest,
+    path: web::Path<String>,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    let db = get_mongo().await;
+    let id = ObjectId::with_string(&path).map_err(|_| ResourceIOError::NotFound)?;
+    let mut doc: Resource<SeaweedFsId> = db
+        .find_resource(&id)
+        .await?
+        .filter(|r| !r.is_trashed())
+        .ok_or(ResourceIOError::NotFound)?;
+    if !doc.can_read(Some(&user)) {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    //Images of others are shown with the watermark of their owner
+    if let Some(storage) = watermarked(&mut doc, &user, &config).await? {
+        return Ok(HttpResponse::Ok()
+            .content_type(doc.rendered_mime().essence_str())
+            .streaming(ResponseStream {
+                stream: storage.read().await,
+            }));
+    }
+
+    //Size of resources stored before it was recorded is unknown
+    let size = doc.get_size() as u64;
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| size > 0)
+        .and_then(|v| parse_range(v, size));
+    let content_type = doc.get_extension().essence_str();
+    match range {
+        Some(Err(())) => Ok(HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+            .finish()),
+        Some(Ok((start, end))) => {
+            let stream = doc.read_range(Some(&user), start, end).await?;
+            Ok(HttpResponse::PartialContent()
+                .content_type(content_type)
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, size),
+                ))
+                .no_chunking(end - start + 1)
+                .streaming(ResponseStream { stream }))
+        }
+        None => {
+            let stream = doc.read(Some(&user)).await?;
+            let mut response = HttpResponse::Ok();
+            response.content_type(content_type);
+            if size > 0 {
+                response
+                    .insert_header((header::ACCEPT_RANGES, "bytes"))
+                    .no_chunking(size);
+            }
+            Ok(response.streaming(ResponseStream { stream }))
+        }
+    }
+}
+
+///Still image of a video or an animation
+pub async fn get_poster(
+    path: web::Path<String>,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    let db = get_mongo().await;
+    let id = ObjectId::with_string(&path).map_err(|_| ResourceIOError::NotFound)?;
+    let mut res: Resource<SeaweedFsId> = db
+        .find_resource(&id)
+        .await?
+        .filter(|r| !r.is_trashed())
+        .ok_or(ResourceIOError::NotFound)?;
+    if !res.can_read(Some(&user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "reading".to_string(),
+        ));
+    }
+    //Animations of others are replaced by their watermarked still
+    if let Some(storage) = watermarked(&mut res, &user, &config).await? {
+        return Ok(HttpResponse::Ok()
+            .content_type(res.rendered_mime().essence_str())
+            .streaming(ResponseStream {
+                stream: storage.read().await,
+            }));
+    }
+    let poster = res.get_poster().ok_or(ResourceIOError::NotFound)?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/jpeg")
+        .streaming(ResponseStream {
+            stream: poster.read().await,
+        }))
+}
+
+///Edit title and description of a resource
+pub async fn update_media(
+    path: web::Path<String>,
+    user: User,
+    req: web::Json<MediaUpdateReq>,
+) -> ResourceResponse {
+    let db = get_mongo().await;
+    let id = ObjectId::with_string(&path).map_err(|_| ResourceIOError::NotFound)?;
+    let mut res: Resource<SeaweedFsId> = db
+        .find_resource(&id)
+        .await?
+        .filter(|r| !r.is_trashed())
+        .ok_or(ResourceIOError::NotFound)?;
+    if !res.can_write(Some(&user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "writing".to_string(),
+        ));
+    }
+    res.update_metadata(&req)?;
+    db.update_metadata(&res).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(Deserialize)]
+pub struct SearchReq {
+    q: String,
+}
+
+///Media readable by the user matching words of title, description, tags, filename or camera
+pub async fn search_media(
+    user: User,
+    search: web::Query<SearchReq>,
+    pagination: web::Query<PaginationOptions>,
+) -> ResourceResponse {
+    if search.q.trim().is_empty() {
+        return Ok(HttpResponse::Ok().json(Vec::<Resource<SeaweedFsId>>::new()));
+    }
+    let db = get_mongo().await;
+    let found = db
+        .search_resources::<SeaweedFsId>(&user.get_id().unwrap(), &search.q, &pagination)
+        .await?;
+    Ok(HttpResponse::Ok().json(found))
+}
+
+const DEFAULT_MAX_DISTANCE: u32 = 10;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarityOptions {
+    ///Maximum number of differing bits between two perceptual hashes
+    max_distance: Option<u32>,
+}
+
+impl SimilarityOptions {
+    fn max_distance(&self) -> u32 {
+        self.max_distance.unwrap_or(DEFAULT_MAX_DISTANCE).min(32)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SimilarMedia {
+    id: ObjectId,
+    distance: u32,
+}
+
+///Images of the user looking like the requested one, closest first
+pub async fn get_similar_media(
+    path: web::Path<String>,
+    user: User,
+    options: web::Query<SimilarityOptions>,
+) -> ResourceResponse {
+    let db = get_mongo().await;
+    let id = ObjectId::with_string(&path).map_err(|_| ResourceIOError::NotFound)?;
+    let res: Resource<SeaweedFsId> = db
+        .find_resource(&id)
+        .await?
+        .filter(|r| !r.is_trashed())
+        .ok_or(ResourceIOError::NotFound)?;
+    if !res.can_read(Some(&user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "reading".to_string(),
+        ));
+    }
+    let phash = match res.get_phash() {
+        Some(h) => h,
+        None => return Ok(HttpResponse::Ok().json(Vec::<SimilarMedia>::new())),
+    };
+
+    let max_distance = options.max_distance();
+    let mut similar: Vec<SimilarMedia> = db
+        .find_owned_phashes(&user.get_id().unwrap())
+        .await?
+        .into_iter()
+        .filter(|p| p.id != id)
+        .map(|p| SimilarMedia {
+            id: p.id,
+            distance: hamming_distance(phash, p.phash as u64),
+        })
+        .filter(|s| s.distance <= max_distance)
+        .collect();
+    similar.sort_by_key(|s| s.distance);
+    Ok(HttpResponse::Ok().json(similar))
+}
+
+///Groups of images of the user that are possible duplicates of each other
+pub async fn get_duplicates(
+    user: User,
+    options: web::Query<SimilarityOptions>,
+) -> ResourceResponse {
+    let db = get_mongo().await;
+    let phashes = db.find_owned_phashes(&user.get_id().unwrap()).await?;
+    let max_distance = options.max_distance();
+    let hashes: Vec<u64> = phashes.iter().map(|p| p.phash as u64).collect();
+    let groups = tokio::task::spawn_blocking(move || group_similar(&hashes, max_distance))
+        .await
+        .unwrap();
+    let groups: Vec<Vec<ObjectId>> = groups
+        .into_iter()
+        .map(|g| g.into_iter().map(|i| phashes[i].id.clone()).collect())
+        .collect();
+    Ok(HttpResponse::Ok().json(groups))
 }
@@ -1,4 +1,19 @@
+mod admin;
+mod animation;
+mod archive;
+mod batch;
+mod edit;
+mod export;
+mod geo;
 mod media;
+mod takeout;
+mod timeline;
+mod trash;
+mod tus;
 mod user;
+mod watermark;
 
-pub use self::{media::config_media, user::config_user};
+pub use self::{
+    admin::config_admin, media::config_media, trash::purge_expired_trash,
+    tus::purge_abandoned_uploads, user::config_user,
+};
@@ -0,0 +1,169 @@
+use super::media::{check_quota, store_resource};
+use crate::{
+    config::Config,
+    models::{MediaUpdateReq, Resource, TakeoutReport, User},
+    tools::{media_type, ResourceIOError, SeaweedFsId, TakeoutIndex, ZipEntry, ZipReader},
+};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use mongodb::bson::oid::ObjectId;
+use std::{fs::File, io, path::PathBuf};
+use tokio::{fs, io::AsyncWriteExt};
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+const ZIP_CONTENT_TYPES: [&str; 2] = ["application/zip", "application/x-zip-compressed"];
+///Sidecars are small, larger JSON files are not read
+const SIDECAR_MAX_SIZE: u64 = 1024 * 1024;
+
+pub fn config_takeout(cfg: &mut web::ServiceConfig) {
+    cfg.route("/takeout", web::post().to(import_takeout));
+}
+
+fn archive_error(e: io::Error) -> ResourceIOError {
+    match e.kind() {
+        io::ErrorKind::InvalidData => ResourceIOError::InvalidUpload(format!("archive: {}", e)),
+        _ => e.into(),
+    }
+}
+
+///Import every media of a ZIP archive sent as the body, such as a Google Photos Takeout.
+///Capture date, description and location of sidecar JSON files take precedence over the
+///ones embedded in media, and media of an album are tagged with its title
+pub async fn import_takeout(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !ZIP_CONTENT_TYPES.contains(&content_type) {
+        return Err(ResourceIOError::UnsupportedMediaType(
+            ZIP_CONTENT_TYPES[0].to_string(),
+        ));
+    }
+    check_quota(&user, 0, config.storage_quota).await?;
+
+    //Entries are read out of order, the archive is staged on disk
+    fs::create_dir_all(&config.upload_dir).await?;
+    let path = config
+        .upload_dir
+        .join(format!("takeout-{}", ObjectId::new().to_hex()));
+    let mut file = fs::File::create(&path).await?;
+    let mut written = 0i64;
+    let mut received = Ok(());
+    while let Some(chunk) = payload.next().await {
+        received = match chunk {
+            Ok(chunk) if written + chunk.len() as i64 > config.max_upload_size => {
+                Err(ResourceIOError::TooLarge)
+            }
+            Ok(chunk) => {
+                written += chunk.len() as i64;
+                file.write_all(&chunk).await.map_err(ResourceIOError::from)
+            }
+            Err(e) => Err(ResourceIOError::InvalidUpload(e.to_string())),
+        };
+        if received.is_err() {
+            break;
+        }
+    }
+    drop(file);
+
+    let report = match received {
+        Ok(()) => import_archive(path.clone(), &user, &config).await,
+        Err(e) => Err(e),
+    };
+    fs::remove_file(&path).await?;
+    Ok(HttpResponse::Ok().json(report?))
+}
+
+///Import media of the archive at path one after the other, stopping once quota is exhausted
+async fn import_archive(
+    path: PathBuf,
+    user: &User,
+    config: &Config,
+) -> Result<TakeoutReport, ResourceIOError> {
+    //Reading the archive is blocking, the reader moves in and out of blocking tasks
+    let (mut zip, index) = tokio::task::spawn_blocking(move || {
+        let mut zip = ZipReader::new(File::open(path)?)?;
+        let mut index = TakeoutIndex::default();
+        let sidecars: Vec<ZipEntry> = zip
+            .entries()
+            .iter()
+            .filter(|e| e.name.ends_with(".json") && e.size <= SIDECAR_MAX_SIZE)
+            .cloned()
+            .collect();
+        for entry in sidecars.iter() {
+            if let Ok(data) = zip.read(entry, SIDECAR_MAX_SIZE) {
+                index.add_json(&entry.name, &data);
+            }
+        }
+        Ok::<_, io::Error>((zip, index))
+    })
+    .await
+    .unwrap()
+    .map_err(archive_error)?;
+
+    let media: Vec<ZipEntry> = zip
+        .entries()
+        .iter()
+        .filter(|e| !e.is_dir() && media_type(&e.name).is_some())
+        .cloned()
+        .collect();
+    let max_size = config.max_upload_size as u64;
+    let mut report = TakeoutReport::default();
+    for entry in media {
+        let name = entry.name.clone();
+        let (returned, data) = tokio::task::spawn_blocking(move || {
+            let data = zip.read(&entry, max_size);
+            (zip, data)
+        })
+        .await
+        .unwrap();
+        zip = returned;
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                report.push_failed(name, e.to_string());
+                continue;
+            }
+        };
+
+        let mut res = Resource::<SeaweedFsId>::new(media_type(&name).unwrap(), user);
+        res.set_filename(name.rsplit('/').next().unwrap_or(&name));
+        let sidecar = index.sidecar(&name);
+        if let Some(taken_at) = sidecar.and_then(|s| s.taken_at()) {
+            res.set_taken_at(taken_at.into());
+        }
+        if let Some(location) = sidecar.and_then(|s| s.location()) {
+            res.set_location(location);
+        }
+        //Metadata Pixure cannot hold is dropped rather than failing the media
+        let _ = res.update_metadata(&MediaUpdateReq {
+            title: None,
+            description: sidecar.and_then(|s| s.description.clone()),
+            tags: None,
+        });
+        if let Some(album) = index.album(&name) {
+            let _ = res.update_metadata(&MediaUpdateReq {
+                title: None,
+                description: None,
+                tags: Some(vec![album.to_string()]),
+            });
+        }
+
+        match store_resource(res, user, data, config).await {
+            Ok(stored) => report.push_imported(name, stored.id, stored.duplicate),
+            Err(ResourceIOError::QuotaExceeded) => {
+                report.push_failed(name, ResourceIOError::QuotaExceeded.to_string());
+                break;
+            }
+            Err(e) => report.push_failed(name, e.to_string()),
+        }
+    }
+    Ok(report)
+}
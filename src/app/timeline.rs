@@ -0,0 +1,83 @@
+use crate::{
+    db::{get_mongo, Granularity, PaginationOptions},
+    models::User,
+    tools::{ResourceIOError, SeaweedFsId},
+};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::Deserialize;
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+pub fn config_timeline(cfg: &mut web::ServiceConfig) {
+    cfg.route("/timeline", web::get().to(get_timeline))
+        .route("/timeline/media", web::get().to(get_timeline_media));
+}
+
+#[derive(Deserialize)]
+pub struct TimelineQuery {
+    #[serde(default)]
+    group: Granularity,
+}
+
+///Period of a timeline bucket, a whole year when month is missing
+#[derive(Deserialize)]
+pub struct PeriodQuery {
+    year: i32,
+    month: Option<u32>,
+    day: Option<u32>,
+}
+
+impl PeriodQuery {
+    ///First instant of the period and first instant after it
+    fn range(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        match (self.month, self.day) {
+            (None, None) => {
+                let from = Utc.ymd_opt(self.year, 1, 1).single()?;
+                let to = Utc.ymd_opt(self.year + 1, 1, 1).single()?;
+                Some((from.and_hms(0, 0, 0), to.and_hms(0, 0, 0)))
+            }
+            (Some(month), None) => {
+                let from = Utc.ymd_opt(self.year, month, 1).single()?;
+                let to = if month == 12 {
+                    Utc.ymd_opt(self.year + 1, 1, 1)
+                } else {
+                    Utc.ymd_opt(self.year, month + 1, 1)
+                }
+                .single()?;
+                Some((from.and_hms(0, 0, 0), to.and_hms(0, 0, 0)))
+            }
+            (Some(month), Some(day)) => {
+                let from = Utc
+                    .ymd_opt(self.year, month, day)
+                    .single()?
+                    .and_hms(0, 0, 0);
+                Some((from, from + Duration::days(1)))
+            }
+            (None, Some(_)) => None,
+        }
+    }
+}
+
+///Number of media per year, month or day of capture
+pub async fn get_timeline(user: User, query: web::Query<TimelineQuery>) -> ResourceResponse {
+    let db = get_mongo().await;
+    let buckets = db.timeline(&user.get_id().unwrap(), query.group).await?;
+    Ok(HttpResponse::Ok().json(buckets))
+}
+
+///Media of a single timeline bucket
+pub async fn get_timeline_media(
+    user: User,
+    period: web::Query<PeriodQuery>,
+    pagination: web::Query<PaginationOptions>,
+) -> ResourceResponse {
+    let (from, to) = period
+        .range()
+        .ok_or_else(|| ResourceIOError::InvalidQuery("period is not a valid date".to_string()))?;
+    let db = get_mongo().await;
+    let found = db
+        .find_resources_between::<SeaweedFsId>(&user.get_id().unwrap(), from, to, &pagination)
+        .await?;
+    Ok(HttpResponse::Ok().json(found))
+}
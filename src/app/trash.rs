@@ -0,0 +1,108 @@
+use super::media::{purge_resource, remove_resource};
+use crate::{
+    db::{get_mongo, PaginationOptions},
+    models::{Resource, User},
+    tools::{ResourceIOError, SeaweedFsId},
+};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use mongodb::bson::oid::ObjectId;
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+///Deleting a resource moves it to the trash, where it stays until restored or purged
+pub fn config_trash(cfg: &mut web::ServiceConfig) {
+    cfg.route("/trash", web::get().to(get_trash))
+        .route("/trash", web::delete().to(empty_trash))
+        .route("{id}/restore", web::post().to(restore_media))
+        .route("{id}", web::delete().to(trash_media));
+}
+
+///Find a resource that user may delete or restore
+async fn find_deletable(id: &str, user: &User) -> Result<Resource<SeaweedFsId>, ResourceIOError> {
+    let id = ObjectId::with_string(id).map_err(|_| ResourceIOError::NotFound)?;
+    let res: Resource<SeaweedFsId> = get_mongo()
+        .await
+        .find_resource(&id)
+        .await?
+        .ok_or(ResourceIOError::NotFound)?;
+    if !res.can_delete(Some(user)) {
+        return Err(ResourceIOError::InsufficientPermissions(
+            "deleting".to_string(),
+        ));
+    }
+    Ok(res)
+}
+
+pub async fn trash_media(path: web::Path<String>, user: User) -> ResourceResponse {
+    let res = find_deletable(&path, &user).await?;
+    if !get_mongo()
+        .await
+        .trash_resource(res.get_id().unwrap())
+        .await?
+    {
+        return Err(ResourceIOError::NotFound);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn restore_media(path: web::Path<String>, user: User) -> ResourceResponse {
+    let res = find_deletable(&path, &user).await?;
+    if !get_mongo()
+        .await
+        .restore_resource(res.get_id().unwrap())
+        .await?
+    {
+        return Err(ResourceIOError::NotFound);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_trash(user: User, pagination: web::Query<PaginationOptions>) -> ResourceResponse {
+    let db = get_mongo().await;
+    let trashed = db
+        .find_trashed_resources::<SeaweedFsId>(&user.get_id().unwrap(), &pagination)
+        .await?;
+    Ok(HttpResponse::Ok().json(trashed))
+}
+
+pub async fn empty_trash(user: User) -> ResourceResponse {
+    let db = get_mongo().await;
+    let trashed = db
+        .find_expired_trash::<SeaweedFsId>(user.get_id().as_ref(), Utc::now())
+        .await?;
+    for res in trashed.iter() {
+        remove_resource(res, &user).await?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+///Purge resources trashed before before, one failing does not keep the others in the trash
+async fn purge_trashed_before(before: DateTime<Utc>) -> Result<usize, ResourceIOError> {
+    let db = get_mongo().await;
+    let expired = db.find_expired_trash::<SeaweedFsId>(None, before).await?;
+    let mut purged = 0;
+    for res in expired.iter() {
+        match purge_resource(res).await {
+            Ok(()) => purged += 1,
+            Err(e) => error!("Cannot purge {}: {}", res.get_id().unwrap().to_hex(), e),
+        }
+    }
+    Ok(purged)
+}
+
+///Purge every hour the resources trashed for longer than retention_days
+pub async fn purge_expired_trash(retention_days: i64) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match purge_trashed_before(Utc::now() - Duration::days(retention_days)).await {
+            Ok(0) => {}
+            Ok(count) => info!("Purged {} trashed resources", count),
+            Err(e) => error!("Cannot purge trash: {}", e),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use super::media::{check_quota, store_staged, UploadResult};
+use crate::{
+    config::Config,
+    db::get_mongo,
+    models::{Resource, Upload, User},
+    tools::{ResourceIOError, SeaweedFsId},
+};
+use actix_web::{
+    http::{header, Method},
+    middleware::DefaultHeaders,
+    web, HttpRequest, HttpResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures::StreamExt;
+use log::{error, info};
+use mime::Mime;
+use mongodb::bson::oid::ObjectId;
+use std::{
+    collections::HashMap,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+///Requests appending to an upload for longer are considered dead, another one can resume it
+const CLAIM_TIMEOUT_MINUTES: i64 = 60;
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+///Resumable uploads following the tus 1.0 protocol, see https://tus.io/protocols/resumable-upload
+pub fn config_tus(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/tus")
+            //Every response carries the version, errors included
+            .wrap(DefaultHeaders::new().header("tus-resumable", TUS_VERSION))
+            .route("", web::method(Method::OPTIONS).to(get_capabilities))
+            .route("", web::post().to(create_upload))
+            .route("/{id}", web::head().to(get_upload_offset))
+            .route("/{id}", web::patch().to(append_upload))
+            .route("/{id}", web::delete().to(terminate_upload)),
+    );
+}
+
+fn header_str<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn header_i64(req: &HttpRequest, name: &str) -> Option<i64> {
+    header_str(req, name)
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v >= 0)
+}
+
+fn has_tus_version(req: &HttpRequest) -> bool {
+    header_str(req, "tus-resumable") == Some(TUS_VERSION)
+}
+
+///Decode `key base64value` pairs separated by commas
+fn parse_metadata(value: &str) -> HashMap<String, String> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next().filter(|k| !k.is_empty())?;
+            let value = base64::decode(parts.next().unwrap_or_default()).ok()?;
+            Some((
+                key.to_string(),
+                String::from_utf8_lossy(&value).into_owned(),
+            ))
+        })
+        .collect()
+}
+
+fn staged_path(config: &Config, id: &ObjectId) -> PathBuf {
+    config.upload_dir.join(id.to_hex())
+}
+
+async fn find_owned_upload(id: &str, user: &User) -> Result<Upload, ResourceIOError> {
+    let id = ObjectId::with_string(id).map_err(|_| ResourceIOError::NotFound)?;
+    let db = get_mongo().await;
+    match db.find_upload(&id).await? {
+        Some(upload) if Some(upload.get_owner()) == user.get_id().as_ref() => Ok(upload),
+        _ => Err(ResourceIOError::NotFound),
+    }
+}
+
+///Turn a complete upload into a resource and drop its staged data
+async fn finalize_upload(
+    upload: &Upload,
+    user: &User,
+    config: &Config,
+) -> Result<UploadResult, ResourceIOError> {
+    let id = upload.get_id().unwrap();
+    let db = get_mongo().await;
+    //A retried final request would store the upload twice
+    let length = upload.get_length();
+    let stale_before = Utc::now() - Duration::minutes(CLAIM_TIMEOUT_MINUTES);
+    if !db.claim_upload(id, length, stale_before).await? {
+        return Err(ResourceIOError::OffsetMismatch);
+    }
+    let path = staged_path(config, id);
+    let extension = upload
+        .get_mime()
+        .parse::<Mime>()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    let mut res = Resource::<SeaweedFsId>::new(extension, user);
+    if let Some(filename) = upload.get_filename() {
+        res.set_filename(filename);
+    }
+    let stored = match store_staged(res, user, &path, config).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            //Released without moving so that the client can complete it again
+            db.advance_upload(id, length, length).await?;
+            return Err(e);
+        }
+    };
+    db.delete_upload(id).await?;
+    fs::remove_file(&path).await?;
+    Ok(stored)
+}
+
+///Write payload to the file at path from offset, up to remaining bytes.
+///Returns the bytes written and whether the payload held more
+async fn append_payload(
+    payload: &mut web::Payload,
+    path: &Path,
+    offset: i64,
+    remaining: i64,
+) -> io::Result<(i64, bool)> {
+    let mut file = fs::OpenOptions::new().write(true).open(path).await?;
+    file.seek(SeekFrom::Start(offset as u64)).await?;
+    let mut written = 0i64;
+    let mut overflow = false;
+    //Bytes received before a connection drop are kept so that the client can resume
+    while let Some(Ok(chunk)) = payload.next().await {
+        if written + chunk.len() as i64 > remaining {
+            overflow = true;
+            break;
+        }
+        file.write_all(&chunk).await?;
+        written += chunk.len() as i64;
+    }
+    file.flush().await?;
+    Ok((written, overflow))
+}
+
+pub async fn get_capabilities(config: web::Data<Config>) -> HttpResponse {
+    HttpResponse::NoContent()
+        .insert_header(("tus-version", TUS_VERSION))
+        .insert_header(("tus-extension", TUS_EXTENSIONS))
+        .insert_header(("tus-max-size", config.max_upload_size.to_string()))
+        .finish()
+}
+
+pub async fn create_upload(
+    req: HttpRequest,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    if !has_tus_version(&req) {
+        return Err(ResourceIOError::UnsupportedVersion);
+    }
+    let length = header_i64(&req, "upload-length")
+        .ok_or_else(|| ResourceIOError::InvalidUpload("Upload-Length is required".to_string()))?;
+    if length > config.max_upload_size {
+        return Err(ResourceIOError::TooLarge);
+    }
+    check_quota(&user, length, config.storage_quota).await?;
+
+    let metadata = parse_metadata(header_str(&req, "upload-metadata").unwrap_or_default());
+    let mime = metadata
+        .get("filetype")
+        .filter(|m| m.parse::<Mime>().is_ok())
+        .cloned()
+        .unwrap_or_else(|| mime::APPLICATION_OCTET_STREAM.to_string());
+    let upload = Upload::new(&user, length, mime, metadata.get("filename").cloned());
+
+    let db = get_mongo().await;
+    let id = db.save_upload(upload.clone()).await?;
+    fs::create_dir_all(&config.upload_dir).await?;
+    fs::File::create(staged_path(&config, &id)).await?;
+
+    let mut response = HttpResponse::Created();
+    response.insert_header((
+        header::LOCATION,
+        format!("{}/{}", req.path().trim_end_matches('/'), id.to_hex()),
+    ));
+    if length == 0 {
+        let upload = db
+            .find_upload(&id)
+            .await?
+            .ok_or(ResourceIOError::NotFound)?;
+        let stored = finalize_upload(&upload, &user, &config).await?;
+        response.insert_header(("pixure-resource-id", stored.id.to_hex()));
+    }
+    Ok(response.finish())
+}
+
+pub async fn get_upload_offset(
+    req: HttpRequest,
+    path: web::Path<String>,
+    user: User,
+) -> ResourceResponse {
+    if !has_tus_version(&req) {
+        return Err(ResourceIOError::UnsupportedVersion);
+    }
+    let upload = find_owned_upload(&path, &user).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("upload-offset", upload.get_offset().to_string()))
+        .insert_header(("upload-length", upload.get_length().to_string()))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .finish())
+}
+
+pub async fn append_upload(
+    req: HttpRequest,
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    if !has_tus_version(&req) {
+        return Err(ResourceIOError::UnsupportedVersion);
+    }
+    if header_str(&req, "content-type") != Some(OFFSET_CONTENT_TYPE) {
+        return Err(ResourceIOError::UnsupportedMediaType(
+            OFFSET_CONTENT_TYPE.to_string(),
+        ));
+    }
+    let upload = find_owned_upload(&path, &user).await?;
+    let offset = header_i64(&req, "upload-offset")
+        .ok_or_else(|| ResourceIOError::InvalidUpload("Upload-Offset is required".to_string()))?;
+    if offset != upload.get_offset() {
+        return Err(ResourceIOError::OffsetMismatch);
+    }
+
+    let id = upload.get_id().unwrap().clone();
+    let db = get_mongo().await;
+    //Requests appending at once would write over each other
+    let stale_before = Utc::now() - Duration::minutes(CLAIM_TIMEOUT_MINUTES);
+    if !db.claim_upload(&id, offset, stale_before).await? {
+        return Err(ResourceIOError::OffsetMismatch);
+    }
+    let appended = append_payload(
+        &mut payload,
+        &staged_path(&config, &id),
+        offset,
+        upload.get_length() - offset,
+    )
+    .await;
+    //Bytes which could not be written are received again from the same offset
+    let written = appended.as_ref().map_or(0, |(written, _)| *written);
+    let new_offset = offset + written;
+    if !db.advance_upload(&id, offset, new_offset).await? {
+        return Err(ResourceIOError::OffsetMismatch);
+    }
+    let (_, overflow) = appended?;
+    if overflow {
+        return Err(ResourceIOError::InvalidUpload(
+            "body exceeds Upload-Length".to_string(),
+        ));
+    }
+
+    let mut response = HttpResponse::NoContent();
+    response.insert_header(("upload-offset", new_offset.to_string()));
+    let upload = db
+        .find_upload(&id)
+        .await?
+        .ok_or(ResourceIOError::NotFound)?;
+    if upload.is_complete() {
+        let stored = finalize_upload(&upload, &user, &config).await?;
+        response.insert_header(("pixure-resource-id", stored.id.to_hex()));
+    }
+    Ok(response.finish())
+}
+
+pub async fn terminate_upload(
+    req: HttpRequest,
+    path: web::Path<String>,
+    user: User,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    if !has_tus_version(&req) {
+        return Err(ResourceIOError::UnsupportedVersion);
+    }
+    let upload = find_owned_upload(&path, &user).await?;
+    let id = upload.get_id().unwrap();
+    if let Err(e) = fs::remove_file(staged_path(&config, id)).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    get_mongo().await.delete_upload(id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn purge_uploads_before(
+    upload_dir: &Path,
+    before: DateTime<Utc>,
+) -> Result<usize, ResourceIOError> {
+    let db = get_mongo().await;
+    let mut purged = 0;
+    for upload in db.find_abandoned_uploads(before).await? {
+        let id = upload.get_id().unwrap();
+        //Skipped when bytes were received meanwhile
+        if !db.delete_abandoned_upload(id, before).await? {
+            continue;
+        }
+        if let Err(e) = fs::remove_file(upload_dir.join(id.to_hex())).await {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+///Drop every hour the uploads which received nothing for retention_hours, with their staged data
+pub async fn purge_abandoned_uploads(upload_dir: PathBuf, retention_hours: i64) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match purge_uploads_before(&upload_dir, Utc::now() - Duration::hours(retention_hours)).await
+        {
+            Ok(0) => {}
+            Ok(count) => info!("Dropped {} abandoned uploads", count),
+            Err(e) => error!("Cannot drop abandoned uploads: {}", e),
+        }
+    }
+}
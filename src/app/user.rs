@@ -1,11 +1,21 @@
+use super::export::{config_export, delete_exports};
+use super::watermark::config_watermark;
 use crate::{
-    db::{get_mongo, PaginationOptions},
-    models::{Sessions, User, UserReq},
-    tools::{SeaweedFsId, UserError},
+    config::{Config, RegistrationMode},
+    db::{get_mongo, is_duplicate_key, PaginationOptions},
+    models::{
+        Account, AccountDeletionReq, Invite, PasswordChangeReq, PasswordReset,
+        PasswordResetConfirmReq, PasswordResetReq, Sessions, User, UserReq, TOKEN_VALIDITY_MINUTES,
+    },
+    tools::{
+        validate_password, LoginThrottle, Mail, Mailer, PasswordBlocklist, SeaweedFsId, UserError,
+        ValidationError,
+    },
 };
 use actix_identity::Identity;
-use actix_web::{web, HttpResponse, Responder};
-use std::sync::RwLock;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use std::sync::{Mutex, RwLock};
 
 type UserResponse = Result<HttpResponse, UserError>;
 
@@ -15,19 +25,64 @@ pub fn config_user(cfg: &mut web::ServiceConfig) {
             .route("/login", web::post().to(login))
             .route("/register", web::post().to(register))
             .route("/logout", web::post().to(logout))
+            .route("/invites", web::get().to(get_invites))
+            .route("/invites", web::post().to(create_invite))
+            .route("/password", web::post().to(change_password))
+            .route("/reset/request", web::post().to(request_password_reset))
+            .route("/reset/confirm", web::post().to(confirm_password_reset))
             .route("/user", web::get().to(get_account))
-            .route("/mediaOwned", web::get().to(get_owned_medias)),
+            .route("/user", web::delete().to(delete_account))
+            .route("/mediaOwned", web::get().to(get_owned_medias))
+            .configure(config_export)
+            .configure(config_watermark),
     );
 }
 
 pub async fn login(
+    req: HttpRequest,
     id: Identity,
     user: web::Json<UserReq>,
     sessions: web::Data<RwLock<Sessions>>,
+    throttle: web::Data<Mutex<LoginThrottle>>,
+    config: web::Data<Config>,
 ) -> UserResponse {
+    let ip = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+    let username = user.get_username();
+    //Checked before hashing anything so that throttled requests stay cheap
+    if let Some(secs) = throttle.lock().unwrap().retry_after(&ip, &username) {
+        return Err(UserError::TooManyAttempts(secs));
+    }
+
     let db = get_mongo().await;
+    //Unknown and locked accounts fail like a wrong password so that usernames cannot be probed
     if let Some(user_mod) = db.get_user(&user).await? {
-        user_mod.login(&user)?;
+        if user_mod.is_locked() {
+            throttle.lock().unwrap().record_failure(&ip, &username);
+            return Err(UserError::MismatchingCredential);
+        }
+        let user_id = user_mod.get_id().unwrap();
+        if let Err(e) = user_mod.login(&user) {
+            throttle.lock().unwrap().record_failure(&ip, &username);
+            let failures = db.record_failed_login(&user_id, &ip).await?;
+            if failures >= config.login_max_failures {
+                db.lock_user(
+                    &user_id,
+                    Utc::now() + Duration::minutes(config.login_lockout_minutes),
+                )
+                .await?;
+            }
+            return Err(e);
+        }
+        if user_mod.disabled {
+            return Err(UserError::AccountDisabled);
+        }
+        throttle.lock().unwrap().record_success(&ip, &username);
+        if user_mod.failed_logins != 0 || user_mod.locked_until.is_some() {
+            db.reset_failed_logins(&user_id).await?;
+        }
         id.remember(user_mod.get_username());
         sessions
             .write()
@@ -36,7 +91,8 @@ pub async fn login(
             .insert(user_mod.get_username(), user_mod);
         Ok(HttpResponse::Ok().append_header(("location", "/")).finish())
     } else {
-        Ok(HttpResponse::Forbidden().finish())
+        throttle.lock().unwrap().record_failure(&ip, &username);
+        Err(UserError::MismatchingCredential)
     }
 }
 
@@ -44,21 +100,53 @@ pub async fn register(
     id: Identity,
     user: web::Json<UserReq>,
     sessions: web::Data<RwLock<Sessions>>,
+    blocklist: web::Data<PasswordBlocklist>,
+    config: web::Data<Config>,
 ) -> UserResponse {
+    if config.registration_mode == RegistrationMode::Closed {
+        return Err(UserError::RegistrationClosed);
+    }
+    user.validate(&blocklist)?;
     let db = get_mongo().await;
-    let user_mod = User::new(&user.0);
+    let mut user_mod = User::new(&user.0);
 
     if db.has_user_by_name(&user_mod).await? {
-        return Ok(HttpResponse::Unauthorized().finish());
+        return Err(UserError::UsernameTaken);
+    }
+    let invite = match (config.registration_mode, user.get_invite()) {
+        (RegistrationMode::InviteOnly, Some(code)) => Some(
+            db.claim_invite(code)
+                .await?
+                .ok_or(UserError::InvalidInvite)?,
+        ),
+        (RegistrationMode::InviteOnly, None) => return Err(UserError::InvalidInvite),
+        _ => None,
+    };
+    user_mod.invited_by = invite.as_ref().map(|i| i.get_creator().clone());
+
+    //The unique index still catches concurrent registrations
+    if let Err(e) = db.save_user(user_mod).await {
+        if let Some(invite) = &invite {
+            db.release_invite(invite.get_code()).await?;
+        }
+        return Err(if is_duplicate_key(&e) {
+            UserError::UsernameTaken
+        } else {
+            e.into()
+        });
+    }
+    //Read back the user so that the session knows its _id
+    let user_saved = db.get_user(&user).await?.ok_or(UserError::UnknownUser)?;
+    if let Some(invite) = &invite {
+        db.set_invite_user(invite.get_code(), &user_saved.get_id().unwrap())
+            .await?;
     }
-    let user_saved = user_mod.clone();
-    db.save_user(user_mod).await?;
     id.remember(user.get_username());
     sessions
         .write()
         .unwrap()
         .map
-        .insert(user.get_username(), user_saved.clone());
+        .insert(user.get_username(), user_saved);
     Ok(HttpResponse::Ok().append_header(("location", "/")).finish())
 }
 
@@ -67,8 +155,144 @@ pub async fn logout(id: Identity) -> UserResponse {
     Ok(HttpResponse::Ok().append_header(("location", "/")).finish())
 }
 
-pub async fn get_account(user: User) -> impl Responder {
-    web::Json(user)
+pub async fn change_password(
+    id: Identity,
+    mut user: User,
+    req: web::Json<PasswordChangeReq>,
+    sessions: web::Data<RwLock<Sessions>>,
+    blocklist: web::Data<PasswordBlocklist>,
+) -> UserResponse {
+    let db = get_mongo().await;
+    user.login(&UserReq::new(user.get_username(), req.old_password.clone()))?;
+    validate_password(&req.new_password, &blocklist)?;
+    user.set_password(&req.new_password);
+    db.set_credential(&user).await?;
+    db.delete_password_resets(&user.get_id().unwrap()).await?;
+    //Sessions are shared by every login of the user, all of them log in again
+    id.forget();
+    sessions.write().unwrap().map.remove(&user.get_username());
+    Ok(HttpResponse::Ok().append_header(("location", "/")).finish())
+}
+
+pub async fn request_password_reset(
+    req: web::Json<PasswordResetReq>,
+    config: web::Data<Config>,
+    mailer: web::Data<dyn Mailer>,
+) -> UserResponse {
+    let db = get_mongo().await;
+    //Always answer the same way so that usernames cannot be probed
+    if let Some(user) = db.get_user_by_name(&req.username).await? {
+        if let Some(email) = user.email.clone() {
+            let (reset, token) = PasswordReset::new(&user);
+            db.save_password_reset(reset).await?;
+            mailer
+                .send(Mail {
+                    to: email,
+                    subject: "Pixure password reset".to_string(),
+                    body: format!(
+                        "A password reset was requested for {}.\n\
+                        Use the following link within {} minutes to choose a new password:\n\
+                        {}/reset?token={}",
+                        user.get_username(),
+                        TOKEN_VALIDITY_MINUTES,
+                        config.public_url,
+                        token
+                    ),
+                })
+                .await?;
+        }
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn confirm_password_reset(
+    req: web::Json<PasswordResetConfirmReq>,
+    sessions: web::Data<RwLock<Sessions>>,
+    blocklist: web::Data<PasswordBlocklist>,
+) -> UserResponse {
+    validate_password(&req.new_password, &blocklist)?;
+    let db = get_mongo().await;
+    let reset = db
+        .take_password_reset(&PasswordReset::hash_token(&req.token))
+        .await?
+        .ok_or(UserError::InvalidResetToken)?;
+    if reset.is_expired() {
+        return Err(UserError::InvalidResetToken);
+    }
+    let mut user = db
+        .get_user_by_id(reset.get_user())
+        .await?
+        .ok_or(UserError::InvalidResetToken)?;
+    user.set_password(&req.new_password);
+    db.set_credential(&user).await?;
+    db.delete_password_resets(reset.get_user()).await?;
+    //Sessions opened with the previous password are dropped
+    sessions.write().unwrap().map.remove(&user.get_username());
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn delete_account(
+    id: Identity,
+    user: User,
+    req: web::Json<AccountDeletionReq>,
+    sessions: web::Data<RwLock<Sessions>>,
+) -> UserResponse {
+    let db = get_mongo().await;
+    user.login(&UserReq::new(user.get_username(), req.password.clone()))?;
+    let user_id = user.get_id().unwrap();
+
+    match &req.transfer_to {
+        Some(username) => {
+            let heir = db
+                .get_user_by_name(username)
+                .await?
+                .ok_or(UserError::UnknownUser)?;
+            if heir.get_id().as_ref() == Some(&user_id) {
+                return Err(ValidationError::SelfTransfer.into());
+            }
+            db.transfer_owned_resources(&user_id, &heir.get_id().unwrap())
+                .await?;
+        }
+        //Resources stay in the trash until purged, as any deleted resource
+        None => db.trash_owned_resources(&user_id).await?,
+    }
+    db.revoke_user_access(&user_id).await?;
+    delete_exports(&user_id).await?;
+    db.delete_password_resets(&user_id).await?;
+    db.delete_user(&user_id).await?;
+
+    id.forget();
+    sessions.write().unwrap().map.remove(&user.get_username());
+    Ok(HttpResponse::Ok().append_header(("location", "/")).finish())
+}
+
+pub async fn create_invite(user: User, config: web::Data<Config>) -> UserResponse {
+    if !config.user_invites && !user.is_admin() {
+        return Err(UserError::InvitesDisabled);
+    }
+    let db = get_mongo().await;
+    let invite = Invite::new(&user);
+    db.save_invite(invite.clone()).await?;
+    Ok(HttpResponse::Ok().json(invite))
+}
+
+pub async fn get_invites(user: User) -> UserResponse {
+    let db = get_mongo().await;
+    let invites = db.find_invites_by_creator(&user.get_id().unwrap()).await?;
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+pub async fn get_account(user: User, config: web::Data<Config>) -> UserResponse {
+    let db = get_mongo().await;
+    //Storage usage changes with every upload, the session copy is not up to date
+    let user = db
+        .get_user_by_id(&user.get_id().unwrap())
+        .await?
+        .ok_or(UserError::UnknownUser)?;
+    Ok(HttpResponse::Ok().json(Account {
+        user,
+        storage_limit: config.storage_quota,
+    }))
 }
 
 pub async fn get_owned_medias(
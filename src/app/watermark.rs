@@ -0,0 +1,232 @@
+use super::media::{discard, read_all};
+use crate::{
+    config::Config,
+    db::get_mongo,
+    models::{
+        EditOperation, Media, Resource, User, Watermark, WatermarkMark, Watermarked, Writable,
+    },
+    tools::{
+        apply_edits, apply_watermark, encode_image, orient, text_mark, ResourceIOError,
+        SeaweedFsId, ValidationError,
+    },
+};
+use actix_web::{web, HttpResponse};
+use image::DynamicImage;
+use mongodb::bson::oid::ObjectId;
+use ring::digest;
+use serde_json::json;
+use tokio::fs;
+
+type ResourceResponse = Result<HttpResponse, ResourceIOError>;
+
+pub fn config_watermark(cfg: &mut web::ServiceConfig) {
+    cfg.route("/watermark", web::put().to(set_watermark))
+        .route("/watermark", web::delete().to(remove_watermark));
+}
+
+///What a mark is drawn from
+enum MarkSource {
+    ///Font data and text
+    Text(Vec<u8>, String),
+    ///Image data with its orientation and edits
+    Image(Vec<u8>, u32, Vec<EditOperation>),
+}
+
+///Image of owner that can be drawn as a watermark
+async fn find_mark_image(
+    id: &str,
+    owner: &ObjectId,
+) -> Result<Option<Resource<SeaweedFsId>>, ResourceIOError> {
+    let id = match ObjectId::with_string(id) {
+        Ok(id) => id,
+        Err(_) => return Ok(None),
+    };
+    Ok(get_mongo()
+        .await
+        .find_resource::<SeaweedFsId>(&id)
+        .await?
+        .filter(|r| {
+            !r.is_trashed() && &r.get_owner() == owner && r.get_extension().type_() == mime::IMAGE
+        }))
+}
+
+///Watermark drawn over images of the user read by others, replacing the previous one
+pub async fn set_watermark(
+    user: User,
+    req: web::Json<Watermark>,
+    config: web::Data<Config>,
+) -> ResourceResponse {
+    req.validate()?;
+    match &req.mark {
+        WatermarkMark::Text { .. } if config.watermark_font.is_none() => {
+            return Err(ValidationError::InvalidWatermark(
+                "text watermarks are not available on this server".to_string(),
+            )
+            .into());
+        }
+        WatermarkMark::Image { resource } => {
+            if find_mark_image(resource, &user.get_id().unwrap())
+                .await?
+                .is_none()
+            {
+                return Err(ValidationError::InvalidWatermark(
+                    "resource is not an image of the user".to_string(),
+                )
+                .into());
+            }
+        }
+        WatermarkMark::Text { .. } => {}
+    }
+    get_mongo()
+        .await
+        .set_watermark(&user.get_id().unwrap(), Some(&req))
+        .await?;
+    Ok(HttpResponse::Ok().json(req.into_inner()))
+}
+
+///Show images of the user as they are again
+pub async fn remove_watermark(user: User) -> ResourceResponse {
+    get_mongo()
+        .await
+        .set_watermark(&user.get_id().unwrap(), None)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+///Image turned upright with its edits applied, None when it cannot be decoded
+fn decode_rendered(
+    data: &[u8],
+    orientation: u32,
+    operations: &[EditOperation],
+) -> Option<DynamicImage> {
+    let img = image::load_from_memory(data).ok()?;
+    apply_edits(orient(img, orientation), operations).ok()
+}
+
+///Digest of everything drawn in the watermarked copy of res
+fn watermark_key(
+    res: &Resource<SeaweedFsId>,
+    watermark: &Watermark,
+    logo: Option<&Resource<SeaweedFsId>>,
+) -> String {
+    let drawn = json!({
+        "watermark": watermark,
+        "orientation": res.get_orientation(),
+        "edits": res.get_edits(),
+        "logo": logo.map(|l| json!({
+            "id": l.get_id(),
+            "hash": l.get_hash(),
+            "orientation": l.get_orientation(),
+            "edits": l.get_edits(),
+        })),
+    });
+    hex::encode(digest::digest(
+        &digest::SHA256,
+        drawn.to_string().as_bytes(),
+    ))
+}
+
+///Storage holding res as shown to user, upright with its edits and the watermark of its owner.
+///None when res is shown as is: user owns it, it is not an image or its owner has no watermark.
+///Fails when the watermark cannot be drawn rather than showing res without it.
+///The copy is rendered once then kept along res until what is drawn changes
+pub async fn watermarked(
+    res: &mut Resource<SeaweedFsId>,
+    user: &User,
+    config: &Config,
+) -> Result<Option<SeaweedFsId>, ResourceIOError> {
+    if res.get_extension().type_() != mime::IMAGE || user.get_id() == Some(res.get_owner()) {
+        return Ok(None);
+    }
+    let db = get_mongo().await;
+    let id = res.get_id().unwrap().clone();
+    let owner = match db.get_user_by_id(&res.get_owner()).await? {
+        Some(owner) => owner,
+        None => return Ok(None),
+    };
+    let watermark = match owner.watermark.clone() {
+        Some(watermark) => watermark,
+        None => {
+            //Watermark was removed, its copies are not needed anymore
+            if let Some(previous) = res.replace_watermarked(None) {
+                if db
+                    .replace_watermarked::<SeaweedFsId>(&id, Some(&previous.key), None)
+                    .await?
+                {
+                    discard(&previous.storage).await;
+                }
+            }
+            return Ok(None);
+        }
+    };
+    let logo = match &watermark.mark {
+        WatermarkMark::Image { resource } => Some(
+            find_mark_image(resource, &res.get_owner())
+                .await?
+                .ok_or(ResourceIOError::WatermarkUnavailable)?,
+        ),
+        WatermarkMark::Text { .. } => None,
+    };
+    let key = watermark_key(res, &watermark, logo.as_ref());
+    if let Some(cached) = res.get_watermarked().filter(|w| w.key == key) {
+        return Ok(Some(cached.storage.clone()));
+    }
+
+    let source = match (&watermark.mark, logo) {
+        (WatermarkMark::Text { text }, _) => match &config.watermark_font {
+            Some(font) => MarkSource::Text(fs::read(font).await?, text.clone()),
+            None => return Err(ResourceIOError::WatermarkUnavailable),
+        },
+        (WatermarkMark::Image { .. }, Some(logo)) => MarkSource::Image(
+            read_all(logo.read(Some(&owner)).await?).await?,
+            logo.get_orientation(),
+            logo.get_edits().to_vec(),
+        ),
+        (WatermarkMark::Image { .. }, None) => return Err(ResourceIOError::WatermarkUnavailable),
+    };
+    let data = read_all(res.read(Some(user)).await?).await?;
+    let orientation = res.get_orientation();
+    let edits = res.get_edits().to_vec();
+    let jpeg = res.rendered_mime() == mime::IMAGE_JPEG;
+    let rendered = tokio::task::spawn_blocking(move || {
+        let mark = match source {
+            MarkSource::Text(font, text) => text_mark(&font, &text)?,
+            MarkSource::Image(data, orientation, edits) => {
+                decode_rendered(&data, orientation, &edits)?.to_rgba8()
+            }
+        };
+        let img = decode_rendered(&data, orientation, &edits)?;
+        encode_image(&apply_watermark(img, &mark, &watermark), jpeg).ok()
+    })
+    .await
+    .unwrap()
+    .ok_or(ResourceIOError::WatermarkUnavailable)?;
+
+    let storage = SeaweedFsId::alloc().await;
+    storage.save(rendered).await;
+    let copy = Watermarked {
+        storage: storage.clone(),
+        key,
+    };
+    let previous = res.get_watermarked().map(|w| w.key.clone());
+    if !db
+        .replace_watermarked(&id, previous.as_deref(), Some(&copy))
+        .await?
+    {
+        //Another read replaced the copy meanwhile, use it when it draws the same
+        storage.delete().await?;
+        let current = db
+            .find_resource::<SeaweedFsId>(&id)
+            .await?
+            .and_then(|r| r.get_watermarked().cloned())
+            .filter(|w| w.key == copy.key)
+            .ok_or(ResourceIOError::WatermarkUnavailable)?;
+        let storage = current.storage.clone();
+        res.replace_watermarked(Some(current));
+        return Ok(Some(storage));
+    }
+    if let Some(previous) = res.replace_watermarked(Some(copy)) {
+        discard(&previous.storage).await;
+    }
+    Ok(Some(storage))
+}
@@ -0,0 +1,137 @@
+use log::LevelFilter;
+use std::{env, path::PathBuf, str::FromStr};
+
+///Which mailer delivers account emails
+#[derive(Debug, Clone)]
+pub enum MailerKind {
+    ///Print emails on stdout
+    Log,
+    ///Append emails to a file
+    File(PathBuf),
+}
+
+///Who is allowed to create an account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegistrationMode {
+    Open,
+    ///A valid invite code is required
+    InviteOnly,
+    Closed,
+}
+
+impl FromStr for RegistrationMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(Self::Open),
+            "invite" => Ok(Self::InviteOnly),
+            "closed" => Ok(Self::Closed),
+            _ => Err(()),
+        }
+    }
+}
+
+///Runtime configuration read from PIXURE_* environment variables
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub public_url: String,
+    pub mailer: MailerKind,
+    ///Consecutive failed logins before the account is locked
+    pub login_max_failures: i32,
+    pub login_lockout_minutes: i64,
+    ///File listing breached passwords refused at registration
+    pub breached_passwords: Option<PathBuf>,
+    ///Usernames granted the admin role at startup
+    pub admins: Vec<String>,
+    pub registration_mode: RegistrationMode,
+    ///Whether users other than admins can create invites
+    pub user_invites: bool,
+    ///Bytes each user can store, unlimited when missing
+    pub storage_quota: Option<i64>,
+    ///Directory staging resumable uploads
+    pub upload_dir: PathBuf,
+    ///Largest resumable upload accepted, in bytes
+    pub max_upload_size: i64,
+    ///Largest remote file imported, in bytes
+    pub import_max_size: i64,
+    pub import_timeout_secs: u64,
+    ///Let imports reach private and loopback addresses
+    pub import_allow_private: bool,
+    ///Days trashed resources are kept before being purged
+    pub trash_retention_days: i64,
+    ///Software decoder extracting poster frames of videos, none are extracted when missing
+    pub ffmpeg: Option<PathBuf>,
+    ///TrueType or OpenType font drawing text watermarks, they are refused when missing
+    pub watermark_font: Option<PathBuf>,
+    ///Hours a resumable upload is kept without receiving anything
+    pub upload_retention_hours: i64,
+    ///Most verbose level reported by background tasks
+    pub log_level: LevelFilter,
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+///Like env_or but a value which does not parse stops startup instead of being ignored,
+///for settings where falling back to the default would be unsafe
+fn env_strict<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value: {}", key, value)),
+        Err(_) => default,
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let mailer = match env::var("PIXURE_MAILER") {
+            Ok(path) if path.starts_with("file:") => {
+                MailerKind::File(PathBuf::from(path.trim_start_matches("file:")))
+            }
+            _ => MailerKind::Log,
+        };
+
+        Self {
+            public_url: env::var("PIXURE_PUBLIC_URL")
+                .unwrap_or_else(|_| "http://localhost".to_string()),
+            mailer,
+            login_max_failures: env_or("PIXURE_LOGIN_MAX_FAILURES", 10),
+            login_lockout_minutes: env_or("PIXURE_LOGIN_LOCKOUT_MINUTES", 15),
+            admins: env::var("PIXURE_ADMINS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            breached_passwords: env::var("PIXURE_BREACHED_PASSWORDS")
+                .ok()
+                .map(PathBuf::from),
+            registration_mode: env_strict("PIXURE_REGISTRATION", RegistrationMode::Open),
+            user_invites: env_or("PIXURE_USER_INVITES", true),
+            storage_quota: env::var("PIXURE_STORAGE_QUOTA_MB")
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .map(|mb| mb * 1024 * 1024),
+            upload_dir: env::var("PIXURE_UPLOAD_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir().join("pixure-uploads")),
+            max_upload_size: env_or("PIXURE_MAX_UPLOAD_MB", 2048i64) * 1024 * 1024,
+            import_max_size: env_or("PIXURE_IMPORT_MAX_MB", 50i64) * 1024 * 1024,
+            import_timeout_secs: env_or("PIXURE_IMPORT_TIMEOUT_SECS", 30),
+            import_allow_private: env_or("PIXURE_IMPORT_ALLOW_PRIVATE", false),
+            trash_retention_days: env_or("PIXURE_TRASH_RETENTION_DAYS", 30),
+            ffmpeg: env::var("PIXURE_FFMPEG").ok().map(PathBuf::from),
+            watermark_font: env::var("PIXURE_WATERMARK_FONT").ok().map(PathBuf::from),
+            upload_retention_hours: env_or("PIXURE_UPLOAD_RETENTION_HOURS", 24),
+            log_level: env_or("PIXURE_LOG", LevelFilter::Info),
+        }
+    }
+}
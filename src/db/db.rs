@@ -1,13 +1,23 @@
 use crate::{
     db::MongoClient,
-    models::{Identifiable, Readable, Resource, User, UserReq, Writable},
+    models::{
+        Blob, BoundingBox, Export, GeoCluster, GeoPoint, Identifiable, Invite, PasswordReset,
+        Readable, Resource, Role, Upload, User, UserReq, Watermark, Watermarked, Writable,
+    },
 };
 
+use chrono::{DateTime, Utc};
 use core::fmt::Debug;
 use mongodb::{
-    bson::{doc, oid::ObjectId, to_bson},
-    error::Result,
-    options::FindOptions,
+    bson::{
+        doc, from_document, oid::ObjectId, spec::BinarySubtype, to_bson, to_document, Binary, Bson,
+        Document,
+    },
+    error::{ErrorKind, Result, WriteFailure},
+    options::{
+        Collation, CountOptions, FindOneAndUpdateOptions, FindOptions, ReturnDocument,
+        UpdateOptions,
+    },
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::convert::TryInto;
@@ -20,8 +30,160 @@ pub struct PaginationOptions {
     max_results: u32,
 }
 
+impl PaginationOptions {
+    fn find_options(&self) -> FindOptions {
+        let limit = self.max_results.min(100);
+        FindOptions::builder()
+            .skip(self.page as i64 * limit as i64)
+            .limit(limit as i64)
+            .build()
+    }
+}
+
+///Perceptual hash of a resource, see Resource::get_phash
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PerceptualHash {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub phash: i64,
+}
+
+///Number of resources and bytes owned by a user
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StorageUsage {
+    pub owner: ObjectId,
+    pub username: Option<String>,
+    pub resources: i64,
+    pub bytes: i64,
+}
+
+///Size of the periods grouping media in a timeline
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Year,
+    Month,
+    #[default]
+    Day,
+}
+
+///Number of media captured during a period, finer fields are missing for coarser granularities
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TimelineBucket {
+    pub year: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub month: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub day: Option<i32>,
+    pub count: i64,
+}
+
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+///Filter matching resources user can read, see Resource::can_read
+fn readable_by(user_id: &ObjectId) -> Document {
+    doc! {"$or": [
+        {"owner": user_id},
+        {"access.user": user_id},
+        {"r_public": true}
+    ]}
+}
+
+///Serialized keys of res, for updates that must not overwrite the other fields
+fn resource_fields<T>(res: &Resource<T>, keys: &[&str]) -> Document
+where
+    T: Readable + Writable + Identifiable + Serialize + Unpin + Debug + Clone,
+{
+    let fields = to_document(res).unwrap();
+    let mut set = Document::new();
+    for key in keys {
+        if let Some(value) = fields.get(key) {
+            set.insert(*key, value.clone());
+        }
+    }
+    set
+}
+
+///Uploads which received nothing since before and that no request appends to
+fn abandoned_since(before: DateTime<Utc>) -> Document {
+    doc! {"$and": [
+        {"$or": [
+            {"updatedAt": {"$lt": before}},
+            {"updatedAt": Bson::Null, "createdAt": {"$lt": before}},
+        ]},
+        {"$or": [{"claimedAt": Bson::Null}, {"claimedAt": {"$lt": before}}]},
+    ]}
+}
+
+///Beyond this latitude the web mercator projection is undefined
+const MERCATOR_MAX_LAT: f64 = 85.051_128_78;
+
+///Aggregation expression of the web mercator tile holding `$location` at zoom
+fn tile_expression(zoom: u32) -> Document {
+    let tiles = 2f64.powi(zoom as i32);
+    let max_tile = tiles - 1.0;
+    let lng = doc! {"$arrayElemAt": ["$location.coordinates", 0]};
+    let lat = doc! {"$degreesToRadians": {"$max": [
+        -MERCATOR_MAX_LAT,
+        {"$min": [MERCATOR_MAX_LAT, {"$arrayElemAt": ["$location.coordinates", 1]}]}
+    ]}};
+    doc! {
+        "x": {"$min": [max_tile, {"$floor": {"$multiply": [
+            {"$divide": [{"$add": [lng, 180.0]}, 360.0]},
+            tiles
+        ]}}]},
+        "y": {"$min": [max_tile, {"$floor": {"$multiply": [
+            {"$divide": [
+                {"$subtract": [1.0, {"$divide": [
+                    {"$ln": {"$add": [{"$tan": lat.clone()}, {"$divide": [1.0, {"$cos": lat}]}]}},
+                    std::f64::consts::PI
+                ]}]},
+                2.0
+            ]},
+            tiles
+        ]}}]}
+    }
+}
+
+///Stages keeping media readable by user and dating them with their capture date,
+///falling back to the upload date, then to the creation of their id
+fn dated_media_stages(user_id: &ObjectId) -> Vec<Document> {
+    let mut filter = readable_by(user_id);
+    filter.insert("deleted_at", Bson::Null);
+    vec![
+        doc! {"$match": filter},
+        doc! {"$addFields": {"date": {"$ifNull": [
+            "$exif.taken_at",
+            {"$ifNull": ["$uploaded_at", {"$toDate": "$_id"}]}
+        ]}}},
+    ]
+}
+
+fn username_collation() -> Collation {
+    Collation::builder().locale("en").strength(2).build()
+}
+
+///Whether a write failed because of a unique index
+pub fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(
+        &e.kind,
+        ErrorKind::WriteError(WriteFailure::WriteError(w)) if w.code == DUPLICATE_KEY_CODE
+    )
+}
+
+fn regex_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 impl MongoClient {
-    pub async fn save_resource<T>(&self, doc: Resource<T>) -> Result<()>
+    pub async fn save_resource<T>(&self, doc: Resource<T>) -> Result<ObjectId>
     where
         T: Readable
             + Writable
@@ -33,8 +195,8 @@ impl MongoClient {
             + Clone,
     {
         let coll = self._database.collection::<Resource<T>>("Media");
-        coll.insert_one(doc, None).await?;
-        Ok(())
+        let result = coll.insert_one(doc, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap().clone())
     }
 
     pub async fn find_resource<T>(&self, id: &ObjectId) -> Result<Option<Resource<T>>>
@@ -77,7 +239,8 @@ impl MongoClient {
         let mut cursor = coll
             .find(
                 doc! {
-                    "owner": user_id
+                    "owner": user_id,
+                    "deleted_at": Bson::Null
                 },
                 FindOptions::builder()
                     .batch_size(pagination.max_results.max(50))
@@ -95,43 +258,1065 @@ impl MongoClient {
         Ok(Some(result))
     }
 
-    pub async fn update_resource<T>(&self, res: &Resource<T>) -> Result<()>
+    pub async fn find_all_owned_resources<T>(&self, user_id: &ObjectId) -> Result<Vec<Resource<T>>>
     where
         T: Readable
             + Writable
             + Identifiable
+            + DeserializeOwned
             + Serialize
             + Unpin
             + Debug
+            + Clone,
+    {
+        let coll = self._database.collection::<Resource<T>>("Media");
+        let mut cursor = coll
+            .find(doc! {"owner": user_id, "deleted_at": Bson::Null}, None)
+            .await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Give ownership of every resource of `from` to `to`, including its own access right
+    ///and the storage it accounts for
+    pub async fn transfer_owned_resources(&self, from: &ObjectId, to: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<Document>("Media");
+        let mut cursor = coll
+            .aggregate(
+                vec![
+                    doc! {"$match": {"owner": from}},
+                    doc! {"$group": {"_id": Bson::Null, "bytes": {"$sum": "$size"}}},
+                ],
+                None,
+            )
+            .await?;
+        if let Some(value) = cursor.next().await {
+            let bytes = value?.get_i64("bytes").unwrap_or(0);
+            self.add_storage_used(to, bytes).await?;
+            self.add_storage_used(from, -bytes).await?;
+        }
+        coll.update_many(
+            doc! {"owner": from},
+            doc! {"$set": {"owner": to, "access.$[previous].user": to}},
+            UpdateOptions::builder()
+                .array_filters(vec![doc! {"previous.user": from}])
+                .build(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    ///Remove every access right granted to user on resources of others
+    pub async fn revoke_user_access(&self, user_id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<Document>("Media");
+        coll.update_many(
+            doc! {"access.user": user_id},
+            doc! {"$pull": {"access": {"user": user_id}}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_owned_phashes(&self, user_id: &ObjectId) -> Result<Vec<PerceptualHash>> {
+        let coll = self._database.collection::<PerceptualHash>("Media");
+        let mut cursor = coll
+            .find(
+                doc! {"owner": user_id, "phash": {"$exists": true}, "deleted_at": Bson::Null},
+                FindOptions::builder()
+                    .projection(doc! {"_id": 1, "phash": 1})
+                    .build(),
+            )
+            .await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Resources readable by user matching a text search, most relevant first
+    pub async fn search_resources<T>(
+        &self,
+        user_id: &ObjectId,
+        query: &str,
+        pagination: &PaginationOptions,
+    ) -> Result<Vec<Resource<T>>>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
             + DeserializeOwned
+            + Serialize
+            + Unpin
+            + Debug
             + Clone,
     {
         let coll = self._database.collection::<Resource<T>>("Media");
+        let mut filter = readable_by(user_id);
+        filter.insert("$text", doc! {"$search": query});
+        filter.insert("deleted_at", Bson::Null);
+        let mut options = pagination.find_options();
+        options.projection = Some(doc! {"score": {"$meta": "textScore"}});
+        options.sort = Some(doc! {"score": {"$meta": "textScore"}});
+        let mut cursor = coll.find(filter, options).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Resources readable by user located in bbox
+    pub async fn find_resources_within<T>(
+        &self,
+        user_id: &ObjectId,
+        bbox: &BoundingBox,
+        pagination: &PaginationOptions,
+    ) -> Result<Vec<Resource<T>>>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + DeserializeOwned
+            + Serialize
+            + Unpin
+            + Debug
+            + Clone,
+    {
+        let coll = self._database.collection::<Resource<T>>("Media");
+        let mut filter = readable_by(user_id);
+        filter.insert("deleted_at", Bson::Null);
+        filter.extend(bbox.to_filter());
+        let mut cursor = coll.find(filter, pagination.find_options()).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Resources readable by user located at most radius meters from center, closest first
+    pub async fn find_resources_near<T>(
+        &self,
+        user_id: &ObjectId,
+        center: &GeoPoint,
+        radius: f64,
+        pagination: &PaginationOptions,
+    ) -> Result<Vec<Resource<T>>>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + DeserializeOwned
+            + Serialize
+            + Unpin
+            + Debug
+            + Clone,
+    {
+        let coll = self._database.collection::<Resource<T>>("Media");
+        let mut filter = readable_by(user_id);
+        filter.insert("deleted_at", Bson::Null);
+        filter.insert(
+            "location",
+            doc! {"$nearSphere": {"$geometry": center.to_bson(), "$maxDistance": radius}},
+        );
+        let mut cursor = coll.find(filter, pagination.find_options()).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Count resources readable by user in bbox per web mercator tile at zoom
+    pub async fn cluster_resources(
+        &self,
+        user_id: &ObjectId,
+        bbox: &BoundingBox,
+        zoom: u32,
+    ) -> Result<Vec<GeoCluster>> {
+        let coll = self._database.collection::<Document>("Media");
+        let mut filter = readable_by(user_id);
+        filter.insert("deleted_at", Bson::Null);
+        filter.extend(bbox.to_filter());
+        let mut cursor = coll
+            .aggregate(
+                vec![
+                    doc! {"$match": filter},
+                    doc! {"$group": {
+                        "_id": tile_expression(zoom),
+                        "count": {"$sum": 1},
+                        "lng": {"$avg": {"$arrayElemAt": ["$location.coordinates", 0]}},
+                        "lat": {"$avg": {"$arrayElemAt": ["$location.coordinates", 1]}},
+                        "sample": {"$first": "$_id"}
+                    }},
+                    doc! {"$project": {
+                        "_id": 0,
+                        "x": {"$toLong": "$_id.x"},
+                        "y": {"$toLong": "$_id.y"},
+                        "count": {"$toLong": "$count"},
+                        "center": ["$lng", "$lat"],
+                        "sample": 1
+                    }},
+                ],
+                None,
+            )
+            .await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(from_document(value?)?);
+        }
+        Ok(result)
+    }
+
+    ///Count media readable by user per period, most recent first
+    pub async fn timeline(
+        &self,
+        user_id: &ObjectId,
+        granularity: Granularity,
+    ) -> Result<Vec<TimelineBucket>> {
+        let coll = self._database.collection::<Document>("Media");
+        let mut period = doc! {"year": {"$year": "$date"}};
+        let mut sort = doc! {"_id.year": -1};
+        if granularity != Granularity::Year {
+            period.insert("month", doc! {"$month": "$date"});
+            sort.insert("_id.month", -1);
+        }
+        if granularity == Granularity::Day {
+            period.insert("day", doc! {"$dayOfMonth": "$date"});
+            sort.insert("_id.day", -1);
+        }
+        let mut pipeline = dated_media_stages(user_id);
+        pipeline.extend(vec![
+            doc! {"$group": {"_id": period, "count": {"$sum": 1}}},
+            doc! {"$sort": sort},
+            doc! {"$project": {
+                "_id": 0,
+                "year": "$_id.year",
+                "month": "$_id.month",
+                "day": "$_id.day",
+                "count": {"$toLong": "$count"}
+            }},
+        ]);
+        let mut cursor = coll.aggregate(pipeline, None).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(from_document(value?)?);
+        }
+        Ok(result)
+    }
+
+    ///Media readable by user dated between from included and to excluded, most recent first
+    pub async fn find_resources_between<T>(
+        &self,
+        user_id: &ObjectId,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        pagination: &PaginationOptions,
+    ) -> Result<Vec<Resource<T>>>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + DeserializeOwned
+            + Serialize
+            + Unpin
+            + Debug
+            + Clone,
+    {
+        let coll = self._database.collection::<Document>("Media");
+        let options = pagination.find_options();
+        let mut pipeline = dated_media_stages(user_id);
+        pipeline.extend(vec![
+            doc! {"$match": {"date": {"$gte": Bson::DateTime(from), "$lt": Bson::DateTime(to)}}},
+            doc! {"$sort": {"date": -1, "_id": -1}},
+            doc! {"$skip": options.skip.unwrap_or(0)},
+            doc! {"$limit": options.limit.unwrap_or(100)},
+            doc! {"$project": {"date": 0}},
+        ]);
+        let mut cursor = coll.aggregate(pipeline, None).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(from_document(value?)?);
+        }
+        Ok(result)
+    }
+
+    pub async fn find_resources<T>(&self, ids: &[ObjectId]) -> Result<Vec<Resource<T>>>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + DeserializeOwned
+            + Serialize
+            + Unpin
+            + Debug
+            + Clone,
+    {
+        let coll = self._database.collection::<Resource<T>>("Media");
+        let mut cursor = coll.find(doc! {"_id": {"$in": ids}}, None).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Run update statements on Media in a single round trip, as the driver has no bulk write.
+    ///Each statement is a document with `q`, `u` and optionally `multi` and `arrayFilters`.
+    ///Returns the number of modified documents
+    pub async fn bulk_update_resources(&self, updates: Vec<Document>) -> Result<i64> {
+        let reply = self
+            ._database
+            .run_command(
+                doc! {"update": "Media", "updates": updates, "ordered": false},
+                None,
+            )
+            .await?;
+        if let Ok(errors) = reply.get_array("writeErrors") {
+            if !errors.is_empty() {
+                let message = format!("bulk update failed: {:?}", errors);
+                return Err(std::io::Error::other(message).into());
+            }
+        }
+        Ok(reply.get_i32("nModified").unwrap_or(0) as i64)
+    }
+
+    ///Move resource to the trash, returns false if it already was
+    pub async fn trash_resource(&self, id: &ObjectId) -> Result<bool> {
+        let coll = self._database.collection::<Document>("Media");
+        let result = coll
+            .update_one(
+                doc! {"_id": id, "deleted_at": Bson::Null},
+                doc! {"$set": {"deleted_at": Bson::DateTime(Utc::now())}},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    ///Move every resource of user to the trash
+    pub async fn trash_owned_resources(&self, user_id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<Document>("Media");
+        coll.update_many(
+            doc! {"owner": user_id, "deleted_at": Bson::Null},
+            doc! {"$set": {"deleted_at": Bson::DateTime(Utc::now())}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    ///Take resource out of the trash, returns false if it was not in it
+    pub async fn restore_resource(&self, id: &ObjectId) -> Result<bool> {
+        let coll = self._database.collection::<Document>("Media");
+        let result = coll
+            .update_one(
+                doc! {"_id": id, "deleted_at": {"$ne": Bson::Null}},
+                doc! {"$unset": {"deleted_at": ""}},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    ///Trashed resources of user, most recently deleted first
+    pub async fn find_trashed_resources<T>(
+        &self,
+        user_id: &ObjectId,
+        pagination: &PaginationOptions,
+    ) -> Result<Vec<Resource<T>>>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + DeserializeOwned
+            + Serialize
+            + Unpin
+            + Debug
+            + Clone,
+    {
+        let coll = self._database.collection::<Resource<T>>("Media");
+        let mut options = pagination.find_options();
+        options.sort = Some(doc! {"deleted_at": -1});
+        let mut cursor = coll
+            .find(
+                doc! {"owner": user_id, "deleted_at": {"$ne": Bson::Null}},
+                options,
+            )
+            .await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Resources in the trash since before `before`, of user when given or of everyone
+    pub async fn find_expired_trash<T>(
+        &self,
+        user_id: Option<&ObjectId>,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Resource<T>>>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + DeserializeOwned
+            + Serialize
+            + Unpin
+            + Debug
+            + Clone,
+    {
+        let coll = self._database.collection::<Resource<T>>("Media");
+        let mut filter = doc! {"deleted_at": {"$ne": Bson::Null, "$lte": Bson::DateTime(before)}};
+        if let Some(id) = user_id {
+            filter.insert("owner", id);
+        }
+        let mut cursor = coll.find(filter, None).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Returns false if the resource was already deleted
+    pub async fn delete_resource(&self, id: &ObjectId) -> Result<bool> {
+        let coll = self._database.collection::<Document>("Media");
+        let result = coll.delete_one(doc! {"_id": id}, None).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    pub async fn storage_usage(&self) -> Result<Vec<StorageUsage>> {
+        let coll = self._database.collection::<Document>("Media");
+        let mut cursor = coll
+            .aggregate(
+                vec![
+                    doc! {"$group": {
+                        "_id": "$owner",
+                        "resources": {"$sum": 1},
+                        "bytes": {"$sum": "$size"}
+                    }},
+                    doc! {"$lookup": {
+                        "from": "User",
+                        "localField": "_id",
+                        "foreignField": "_id",
+                        "as": "user"
+                    }},
+                    doc! {"$project": {
+                        "_id": 0,
+                        "owner": "$_id",
+                        "username": {"$arrayElemAt": ["$user.username", 0]},
+                        "resources": {"$toLong": "$resources"},
+                        "bytes": {"$toLong": "$bytes"}
+                    }},
+                    doc! {"$sort": {"resources": -1}},
+                ],
+                None,
+            )
+            .await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(from_document(value?)?);
+        }
+        Ok(result)
+    }
+
+    ///Save the title, description and tags of res, leaving fields written by others untouched
+    pub async fn update_metadata<T>(&self, res: &Resource<T>) -> Result<()>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + Serialize
+            + Unpin
+            + Debug
+            + DeserializeOwned
+            + Clone,
+    {
+        let coll = self._database.collection::<Document>("Media");
         coll.update_one(
             doc! {"_id": res.get_id().unwrap()},
-            doc! {"$set": to_bson(res).unwrap()},
+            doc! {"$set": resource_fields(res, &["title", "description", "tags"])},
             None,
         )
         .await?;
         Ok(())
     }
 
+    ///Save edits, versions and rendered data of res unless another edit recorded its last
+    ///version first. Returns whether it did
+    pub async fn update_edits<T>(&self, res: &Resource<T>) -> Result<bool>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + Serialize
+            + Unpin
+            + Debug
+            + DeserializeOwned
+            + Clone,
+    {
+        let coll = self._database.collection::<Document>("Media");
+        let number = res.get_versions().last().map_or(0, |v| v.number);
+        let result = coll
+            .update_one(
+                doc! {"_id": res.get_id().unwrap(), "versions.number": {"$ne": number}},
+                doc! {"$set": {
+                    "edits": to_bson(res.get_edits()).unwrap(),
+                    "versions": to_bson(res.get_versions()).unwrap(),
+                    "rendered": to_bson(&res.get_rendered()).unwrap(),
+                }},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    ///Save the data of res, along with its hash, size, type and filename, unless the stored
+    ///data is not the one hashed previous anymore. Returns whether it did
+    pub async fn replace_data<T>(&self, res: &Resource<T>, previous: Option<&str>) -> Result<bool>
+    where
+        T: Readable
+            + Writable
+            + Identifiable
+            + Serialize
+            + Unpin
+            + Debug
+            + DeserializeOwned
+            + Clone,
+    {
+        let coll = self._database.collection::<Document>("Media");
+        let set = resource_fields(
+            res,
+            &["_storage", "sha256", "size", "extension", "filename"],
+        );
+        let result = coll
+            .update_one(
+                doc! {"_id": res.get_id().unwrap(), "sha256": previous.map_or(Bson::Null, Bson::from)},
+                doc! {"$set": set},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    ///Replace the watermarked copy of resource id unless it is not the one with key previous
+    ///anymore, None for no copy. Returns whether it did
+    pub async fn replace_watermarked<T>(
+        &self,
+        id: &ObjectId,
+        previous: Option<&str>,
+        watermarked: Option<&Watermarked<T>>,
+    ) -> Result<bool>
+    where
+        T: Serialize,
+    {
+        let coll = self._database.collection::<Document>("Media");
+        let filter = match previous {
+            Some(key) => doc! {"_id": id, "watermarked.key": key},
+            None => doc! {"_id": id, "watermarked": Bson::Null},
+        };
+        let result = coll
+            .update_one(
+                filter,
+                doc! {"$set": {"watermarked": to_bson(&watermarked).unwrap()}},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
     pub async fn get_user(&self, user: &UserReq) -> Result<Option<User>> {
         let coll = self._database.collection::<User>("User");
         coll.find_one(doc! {"username": user.get_username()}, None)
             .await
     }
 
+    pub async fn get_user_by_name(&self, username: &str) -> Result<Option<User>> {
+        let coll = self._database.collection::<User>("User");
+        coll.find_one(doc! {"username": username}, None).await
+    }
+
+    pub async fn get_user_by_id(&self, id: &ObjectId) -> Result<Option<User>> {
+        let coll = self._database.collection::<User>("User");
+        coll.find_one(doc! {"_id": id}, None).await
+    }
+
+    ///List users, optionally only those whose username contains search
+    pub async fn find_users(
+        &self,
+        search: Option<&str>,
+        pagination: &PaginationOptions,
+    ) -> Result<Vec<User>> {
+        let coll = self._database.collection::<User>("User");
+        let filter = match search {
+            Some(s) => doc! {"username": {"$regex": regex_escape(s), "$options": "i"}},
+            None => doc! {},
+        };
+        let mut cursor = coll.find(filter, pagination.find_options()).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    pub async fn set_user_disabled(&self, id: &ObjectId, disabled: bool) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        coll.update_one(
+            doc! {"_id": id},
+            doc! {"$set": {"disabled": disabled}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    ///Replace the watermark of a user, removing it when None
+    pub async fn set_watermark(&self, id: &ObjectId, watermark: Option<&Watermark>) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        let update = match watermark {
+            Some(watermark) => doc! {"$set": {"watermark": to_bson(watermark).unwrap()}},
+            None => doc! {"$unset": {"watermark": ""}},
+        };
+        coll.update_one(doc! {"_id": id}, update, None).await?;
+        Ok(())
+    }
+
+    pub async fn set_role_by_names(&self, usernames: &[String], role: Role) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        coll.update_many(
+            doc! {"username": {"$in": usernames}},
+            doc! {"$set": {"role": to_bson(&role).unwrap()}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn add_storage_used(&self, id: &ObjectId, bytes: i64) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        coll.update_one(
+            doc! {"_id": id},
+            doc! {"$inc": {"storage_used": bytes}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    ///Account for bytes stored by user if its quota allows it, returns whether it did
+    pub async fn reserve_storage(
+        &self,
+        id: &ObjectId,
+        bytes: i64,
+        quota: Option<i64>,
+    ) -> Result<bool> {
+        let coll = self._database.collection::<User>("User");
+        let filter = match quota {
+            Some(quota) => doc! {"_id": id, "storage_used": {"$lte": quota - bytes}},
+            None => doc! {"_id": id},
+        };
+        let result = coll
+            .update_one(filter, doc! {"$inc": {"storage_used": bytes}}, None)
+            .await?;
+        //An empty file modifies nothing but still fits
+        Ok(result.matched_count == 1)
+    }
+
+    ///Save the password of user, the other fields of a session copy may be stale
+    pub async fn set_credential(&self, user: &User) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        let fields = to_document(user).unwrap();
+        coll.update_one(
+            doc! {"_id": user.get_id().unwrap()},
+            doc! {"$set": {"credential": fields.get("credential").unwrap()}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    ///Bump failed login counters of user, returns the consecutive failures count
+    pub async fn record_failed_login(&self, id: &ObjectId, ip: &str) -> Result<i32> {
+        let coll = self._database.collection::<User>("User");
+        let user = coll
+            .find_one_and_update(
+                doc! {"_id": id},
+                doc! {
+                    "$inc": {"failed_logins": 1, "total_failed_logins": 1},
+                    "$set": {"last_failed_login": Bson::DateTime(Utc::now()), "last_failed_ip": ip}
+                },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?;
+        Ok(user.map(|u| u.failed_logins).unwrap_or(0))
+    }
+
+    pub async fn lock_user(&self, id: &ObjectId, until: DateTime<Utc>) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        coll.update_one(
+            doc! {"_id": id},
+            doc! {"$set": {"locked_until": until, "failed_logins": 0}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn reset_failed_logins(&self, id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        coll.update_one(
+            doc! {"_id": id},
+            doc! {"$set": {"failed_logins": 0, "locked_until": Bson::Null}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_user(&self, id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<User>("User");
+        coll.delete_one(doc! {"_id": id}, None).await?;
+        Ok(())
+    }
+
     pub async fn save_user(&self, user: User) -> Result<()> {
         let coll = self._database.collection::<User>("User");
         coll.insert_one(user, None).await?;
         Ok(())
     }
 
+    ///Whether user owns a resource holding the bytes of hash
+    pub async fn has_owned_hash(&self, user_id: &ObjectId, hash: &str) -> Result<bool> {
+        let coll = self._database.collection::<Document>("Media");
+        coll.count_documents(
+            doc! {"owner": user_id, "sha256": hash, "deleted_at": Bson::Null},
+            None,
+        )
+        .await
+        .map(|c| c != 0)
+    }
+
+    ///Usernames are compared case insensitively, as the unique username index does
     pub async fn has_user_by_name(&self, user: &User) -> Result<bool> {
         let coll = self._database.collection::<User>("User");
-        coll.count_documents(doc! {"username": user.get_username()}, None)
+        coll.count_documents(
+            doc! {"username": user.get_username()},
+            CountOptions::builder()
+                .collation(username_collation())
+                .build(),
+        )
+        .await
+        .map(|c| c != 0)
+    }
+
+    pub async fn save_password_reset(&self, reset: PasswordReset) -> Result<()> {
+        let coll = self._database.collection::<PasswordReset>("PasswordReset");
+        coll.insert_one(reset, None).await?;
+        Ok(())
+    }
+
+    ///Find a reset by its token digest and remove it, so that a token is used once
+    pub async fn take_password_reset(&self, token_hash: &[u8]) -> Result<Option<PasswordReset>> {
+        let coll = self._database.collection::<PasswordReset>("PasswordReset");
+        coll.find_one_and_delete(
+            doc! {"token_hash": Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: token_hash.to_vec(),
+            }},
+            None,
+        )
+        .await
+    }
+
+    pub async fn delete_password_resets(&self, user_id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<PasswordReset>("PasswordReset");
+        coll.delete_many(doc! {"user": user_id}, None).await?;
+        Ok(())
+    }
+
+    pub async fn save_invite(&self, invite: Invite) -> Result<()> {
+        let coll = self._database.collection::<Invite>("Invite");
+        coll.insert_one(invite, None).await?;
+        Ok(())
+    }
+
+    pub async fn find_invites_by_creator(&self, user_id: &ObjectId) -> Result<Vec<Invite>> {
+        let coll = self._database.collection::<Invite>("Invite");
+        let mut cursor = coll.find(doc! {"createdBy": user_id}, None).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Atomically mark an unused invite as used, returns None when it cannot be used
+    pub async fn claim_invite(&self, code: &str) -> Result<Option<Invite>> {
+        let coll = self._database.collection::<Invite>("Invite");
+        coll.find_one_and_update(
+            doc! {"code": code, "usedAt": Bson::Null},
+            doc! {"$set": {"usedAt": Bson::DateTime(Utc::now())}},
+            None,
+        )
+        .await
+    }
+
+    ///Give back an invite claimed by a registration that failed
+    pub async fn release_invite(&self, code: &str) -> Result<()> {
+        let coll = self._database.collection::<Invite>("Invite");
+        coll.update_one(
+            doc! {"code": code},
+            doc! {"$set": {"usedAt": Bson::Null}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_invite_user(&self, code: &str, user_id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<Invite>("Invite");
+        coll.update_one(
+            doc! {"code": code},
+            doc! {"$set": {"usedBy": user_id}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn save_blob<T>(&self, blob: Blob<T>) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Debug,
+    {
+        let coll = self._database.collection::<Blob<T>>("Blob");
+        coll.insert_one(blob, None).await?;
+        Ok(())
+    }
+
+    ///Take a reference on the blob holding these bytes, if they are already stored
+    pub async fn acquire_blob<T>(&self, hash: &str) -> Result<Option<Blob<T>>>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Debug,
+    {
+        let coll = self._database.collection::<Blob<T>>("Blob");
+        coll.find_one_and_update(doc! {"_id": hash}, doc! {"$inc": {"refs": 1}}, None)
             .await
-            .map(|c| c != 0)
+    }
+
+    ///Drop a reference on a blob, returns whether its storage is not used anymore
+    pub async fn release_blob<T>(&self, hash: &str) -> Result<bool>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Debug,
+    {
+        let coll = self._database.collection::<Blob<T>>("Blob");
+        let blob = coll
+            .find_one_and_update(
+                doc! {"_id": hash},
+                doc! {"$inc": {"refs": -1}},
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?;
+        match blob {
+            Some(blob) if blob.get_refs() > 0 => Ok(false),
+            //Only removed if nobody acquired it meanwhile
+            Some(_) => coll
+                .delete_one(doc! {"_id": hash, "refs": {"$lte": 0}}, None)
+                .await
+                .map(|r| r.deleted_count == 1),
+            None => Ok(true),
+        }
+    }
+
+    pub async fn save_upload(&self, upload: Upload) -> Result<ObjectId> {
+        let coll = self._database.collection::<Upload>("Upload");
+        let result = coll.insert_one(upload, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap().clone())
+    }
+
+    pub async fn find_upload(&self, id: &ObjectId) -> Result<Option<Upload>> {
+        let coll = self._database.collection::<Upload>("Upload");
+        coll.find_one(doc! {"_id": id}, None).await
+    }
+
+    ///Reserve an upload at offset for a single request appending to it.
+    ///Reservations older than stale_before are from requests that did not finish
+    pub async fn claim_upload(
+        &self,
+        id: &ObjectId,
+        offset: i64,
+        stale_before: DateTime<Utc>,
+    ) -> Result<bool> {
+        let coll = self._database.collection::<Upload>("Upload");
+        let result = coll
+            .update_one(
+                doc! {
+                    "_id": id,
+                    "offset": offset,
+                    "$or": [{"claimedAt": Bson::Null}, {"claimedAt": {"$lt": stale_before}}],
+                },
+                doc! {"$set": {"claimedAt": Bson::DateTime(Utc::now())}},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    ///Move the offset of a claimed upload and release it, only if no other request
+    ///moved it meanwhile
+    pub async fn advance_upload(&self, id: &ObjectId, from: i64, to: i64) -> Result<bool> {
+        let coll = self._database.collection::<Upload>("Upload");
+        let result = coll
+            .update_one(
+                doc! {"_id": id, "offset": from},
+                doc! {"$set": {
+                    "offset": to,
+                    "updatedAt": Bson::DateTime(Utc::now()),
+                    "claimedAt": Bson::Null,
+                }},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    ///Uploads which received nothing since before
+    pub async fn find_abandoned_uploads(&self, before: DateTime<Utc>) -> Result<Vec<Upload>> {
+        let coll = self._database.collection::<Upload>("Upload");
+        let mut cursor = coll.find(abandoned_since(before), None).await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Delete an upload unless it received bytes since before, returns whether it did
+    pub async fn delete_abandoned_upload(
+        &self,
+        id: &ObjectId,
+        before: DateTime<Utc>,
+    ) -> Result<bool> {
+        let coll = self._database.collection::<Upload>("Upload");
+        let mut filter = abandoned_since(before);
+        filter.insert("_id", id);
+        let result = coll.delete_one(filter, None).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    pub async fn delete_upload(&self, id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<Upload>("Upload");
+        coll.delete_one(doc! {"_id": id}, None).await?;
+        Ok(())
+    }
+
+    ///Fail pending exports of user created before, their build did not finish
+    pub async fn fail_stale_exports(
+        &self,
+        user_id: &ObjectId,
+        before: DateTime<Utc>,
+    ) -> Result<()> {
+        let coll = self._database.collection::<Document>("Export");
+        coll.update_many(
+            doc! {"owner": user_id, "status": "pending", "createdAt": {"$lt": before}},
+            doc! {"$set": {"status": "failed", "finishedAt": Bson::DateTime(Utc::now())}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    ///Insert export, failing with a duplicate key when its owner has a pending one
+    pub async fn save_export<T>(&self, export: Export<T>) -> Result<ObjectId>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Debug,
+    {
+        let coll = self._database.collection::<Export<T>>("Export");
+        let result = coll.insert_one(export, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap().clone())
+    }
+
+    pub async fn find_export<T>(&self, id: &ObjectId) -> Result<Option<Export<T>>>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Debug,
+    {
+        let coll = self._database.collection::<Export<T>>("Export");
+        coll.find_one(doc! {"_id": id}, None).await
+    }
+
+    ///Exports of user, most recent first
+    pub async fn find_exports_by_owner<T>(&self, user_id: &ObjectId) -> Result<Vec<Export<T>>>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Debug,
+    {
+        let coll = self._database.collection::<Export<T>>("Export");
+        let mut cursor = coll
+            .find(
+                doc! {"owner": user_id},
+                FindOptions::builder().sort(doc! {"createdAt": -1}).build(),
+            )
+            .await?;
+        let mut result = Vec::new();
+        while let Some(value) = cursor.next().await {
+            result.push(value?);
+        }
+        Ok(result)
+    }
+
+    ///Mark export ready, its archive being held by storage.
+    ///Returns false if the export was deleted meanwhile
+    pub async fn finish_export<T>(
+        &self,
+        id: &ObjectId,
+        storage: &T,
+        size: i64,
+        media: i64,
+    ) -> Result<bool>
+    where
+        T: Serialize,
+    {
+        let coll = self._database.collection::<Document>("Export");
+        let result = coll
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$set": {
+                    "status": "ready",
+                    "finishedAt": Bson::DateTime(Utc::now()),
+                    "storage": to_bson(storage)?,
+                    "size": size,
+                    "media": media
+                }},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    pub async fn fail_export(&self, id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<Document>("Export");
+        coll.update_one(
+            doc! {"_id": id},
+            doc! {"$set": {"status": "failed", "finishedAt": Bson::DateTime(Utc::now())}},
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_export(&self, id: &ObjectId) -> Result<()> {
+        let coll = self._database.collection::<Document>("Export");
+        coll.delete_one(doc! {"_id": id}, None).await?;
+        Ok(())
     }
 }
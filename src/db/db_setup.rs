@@ -1,43 +1,67 @@
-use mongodb::{bson::doc, options::ClientOptions, Client, Database};
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, Document},
+    error::Result,
+    options::ClientOptions,
+    Client, Database,
+};
 use once_cell::sync::OnceCell;
 use tokio::sync::Mutex;
 
 static MONGO: OnceCell<MongoClient> = OnceCell::new();
-static MONGO_INITIALIZED: OnceCell<Mutex<bool>> = OnceCell::new();
+static MONGO_INITIALIZED: OnceCell<Mutex<()>> = OnceCell::new();
 
 pub struct MongoClient {
     pub(in crate::db) _database: Database,
 }
 
+///Groups of usernames that differ only by case, which the unique username index refuses
+async fn case_variant_usernames(database: &Database) -> Result<Vec<Vec<String>>> {
+    let mut cursor = database
+        .collection::<Document>("User")
+        .aggregate(
+            vec![
+                doc! {"$group": {"_id": {"$toLower": "$username"}, "usernames": {"$push": "$username"}}},
+                doc! {"$match": {"usernames.1": {"$exists": true}}},
+            ],
+            None,
+        )
+        .await?;
+    let mut result = Vec::new();
+    while let Some(value) = cursor.next().await {
+        let usernames = value?
+            .get_array("usernames")
+            .map(|a| {
+                a.iter()
+                    .filter_map(|u| u.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+        result.push(usernames);
+    }
+    Ok(result)
+}
+
 pub async fn get_mongo() -> &'static MongoClient {
     if let Some(c) = MONGO.get() {
         return c;
     }
 
-    let initializing_mutex = MONGO_INITIALIZED.get_or_init(|| tokio::sync::Mutex::new(false));
-
-    let mut initialized = initializing_mutex.lock().await;
+    let initializing_mutex = MONGO_INITIALIZED.get_or_init(|| tokio::sync::Mutex::new(()));
 
-    if !*initialized {
-        if let Ok(client_options) =
-            ClientOptions::parse("mongodb://localhost:27017/?appName=Pixure").await
-        {
-            if let Ok(client) = Client::with_options(client_options) {
-                if MONGO
-                    .set(MongoClient {
-                        _database: client.database("Pixure"),
-                    })
-                    .is_ok()
-                {
-                    *initialized = true;
-                }
-            }
-        }
+    let initializing = initializing_mutex.lock().await;
+    //Another caller finished initializing while this one waited
+    if let Some(c) = MONGO.get() {
+        return c;
     }
-    MONGO
-        .get()
-        .unwrap()
-        ._database
+
+    let client_options = ClientOptions::parse("mongodb://localhost:27017/?appName=Pixure")
+        .await
+        .expect("Cannot parse MongoDB address");
+    let client = Client::with_options(client_options).expect("Cannot connect to MongoDB");
+    //Published only once set up, so that a failing setup fails every call
+    let database = client.database("Pixure");
+    database
         .run_command(
             doc! {
                 "createIndexes": "Media",
@@ -47,12 +71,92 @@ pub async fn get_mongo() -> &'static MongoClient {
                         "name": "access_index",
                         "unique": false
                     },
+                    {
+                        "key": {
+                            "title": "text",
+                            "description": "text",
+                            "tags": "text",
+                            "filename": "text",
+                            "exif.make": "text",
+                            "exif.model": "text",
+                            "exif.lens": "text"
+                        },
+                        "name": "search_index",
+                        "weights": { "title": 10, "tags": 5, "description": 2 }
+                    },
+                    {
+                        "key": { "location": "2dsphere" },
+                        "name": "location_index"
+                    },
+                ]
+            },
+            None,
+        )
+        .await
+        .expect("Cannot create index");
+    let duplicates = case_variant_usernames(&database)
+        .await
+        .expect("Cannot check usernames");
+    if !duplicates.is_empty() {
+        panic!(
+            "Usernames must be unique regardless of case, rename all but one of each group \
+            before starting: {:?}",
+            duplicates
+        );
+    }
+    database
+        .run_command(
+            doc! {
+                "createIndexes": "User",
+                "indexes": [
+                    {
+                        "key": { "username": 1 },
+                        "name": "username_index",
+                        "unique": true,
+                        "collation": { "locale": "en", "strength": 2 }
+                    },
+                ]
+            },
+            None,
+        )
+        .await
+        .expect("Cannot create index");
+    database
+        .run_command(
+            doc! {
+                "createIndexes": "Invite",
+                "indexes": [
+                    {
+                        "key": { "code": 1 },
+                        "name": "code_index",
+                        "unique": true
+                    },
+                ]
+            },
+            None,
+        )
+        .await
+        .expect("Cannot create index");
+    database
+        .run_command(
+            doc! {
+                "createIndexes": "Export",
+                "indexes": [
+                    {
+                        "key": { "owner": 1 },
+                        "name": "pending_export_index",
+                        "unique": true,
+                        "partialFilterExpression": { "status": "pending" }
+                    },
                 ]
             },
             None,
         )
         .await
         .expect("Cannot create index");
-    drop(initialized);
+    let _ = MONGO.set(MongoClient {
+        _database: database,
+    });
+    drop(initializing);
     MONGO.get().unwrap()
 }
@@ -1,11 +1,17 @@
 use actix_identity::{CookieIdentityPolicy, IdentityService};
 use actix_web::{web::Data, App, HttpServer};
-use app::{config_media, config_user};
-use std::sync::RwLock;
+use app::{
+    config_admin, config_media, config_user, purge_abandoned_uploads, purge_expired_trash,
+};
+use config::Config;
+use std::sync::{Mutex, RwLock};
+use tools::{init_logger, new_mailer, LoginThrottle, Mailer, PasswordBlocklist};
 
-use crate::models::Sessions;
+use crate::db::get_mongo;
+use crate::models::{Role, Sessions};
 
 mod app;
+mod config;
 mod db;
 mod models;
 mod tools;
@@ -14,11 +20,36 @@ mod tools;
 async fn main() -> std::io::Result<()> {
     const PORT: i32 = 80;
 
+    let config = Config::from_env();
+    init_logger(config.log_level);
+    //Set up the database before serving anything, refusing to start when it cannot be
+    let db = get_mongo().await;
+    if !config.admins.is_empty() {
+        db.set_role_by_names(&config.admins, Role::Admin)
+            .await
+            .expect("Cannot grant admin role");
+    }
+    let mailer: Data<dyn Mailer> = Data::from(new_mailer(&config.mailer));
+    let blocklist: Data<PasswordBlocklist> = Data::new(match &config.breached_passwords {
+        Some(path) => PasswordBlocklist::from_file(path)?,
+        None => Default::default(),
+    });
+    actix_web::rt::spawn(purge_expired_trash(config.trash_retention_days));
+    actix_web::rt::spawn(purge_abandoned_uploads(
+        config.upload_dir.clone(),
+        config.upload_retention_hours,
+    ));
+    let config = Data::new(config);
     let sessions: Data<RwLock<Sessions>> = Data::new(RwLock::new(Default::default()));
+    let throttle: Data<Mutex<LoginThrottle>> = Data::new(Mutex::new(Default::default()));
 
     HttpServer::new(move || {
         App::new()
+            .app_data(config.clone())
+            .app_data(mailer.clone())
+            .app_data(blocklist.clone())
             .app_data(sessions.clone())
+            .app_data(throttle.clone())
             .wrap(IdentityService::new(
                 CookieIdentityPolicy::new(&[0; 32])
                     .name("pixure-id")
@@ -26,6 +57,7 @@ async fn main() -> std::io::Result<()> {
             ))
             .configure(config_media)
             .configure(config_user)
+            .configure(config_admin)
     })
     .bind(format!("0.0.0.0:{}", PORT))?
     .run()
@@ -0,0 +1,67 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+///Operation applied to every resource of a batch
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BatchOperation {
+    ///Move to the trash
+    Delete,
+    AddTag {
+        tag: String,
+    },
+    ///Change the public flags that are given
+    SetPublic {
+        read: Option<bool>,
+        write: Option<bool>,
+    },
+    ///Give username access, replacing the access it already had
+    GrantAccess {
+        username: String,
+        #[serde(default)]
+        write: bool,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchReq {
+    pub ids: Vec<String>,
+    pub operation: BatchOperation,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchStatus {
+    Ok,
+    NotFound,
+    Forbidden,
+    ///Operation does not apply to the resource
+    Skipped,
+}
+
+///Outcome of a batch operation for one of the requested ids
+#[derive(Serialize, Debug)]
+pub struct BatchItemResult {
+    pub id: String,
+    pub status: BatchStatus,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchReport {
+    pub modified: i64,
+    pub items: Vec<BatchItemResult>,
+}
+
+impl BatchItemResult {
+    pub fn new(id: &str, status: BatchStatus) -> Self {
+        Self {
+            id: id.to_string(),
+            status,
+        }
+    }
+
+    pub fn object_id(&self) -> Option<ObjectId> {
+        ObjectId::with_string(&self.id).ok()
+    }
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+///Stored data shared by every resource having the same content,
+///identified by the SHA-256 of its bytes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Blob<StorageType> {
+    #[serde(rename = "_id")]
+    hash: String,
+    storage: StorageType,
+    size: i64,
+    ///Number of resources using this storage
+    refs: i64,
+}
+
+impl<StorageType> Blob<StorageType> {
+    pub fn new(hash: String, storage: StorageType, size: i64) -> Self {
+        Self {
+            hash,
+            storage,
+            size,
+            refs: 1,
+        }
+    }
+
+    pub fn get_storage(&self) -> &StorageType {
+        &self.storage
+    }
+
+    pub fn get_refs(&self) -> i64 {
+        self.refs
+    }
+}
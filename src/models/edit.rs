@@ -0,0 +1,99 @@
+use chrono::Utc;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::tools::ValidationError;
+
+pub const EDITS_MAX: usize = 50;
+///Older versions are forgotten, the original one is always kept
+pub const VERSIONS_MAX: usize = 100;
+pub const BRIGHTNESS_MAX: i32 = 100;
+pub const EXPOSURE_MAX_STOPS: f64 = 5.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FlipAxis {
+    Horizontal,
+    Vertical,
+}
+
+///Change applied to an image, in order, on the result of the previous ones
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum EditOperation {
+    ///Keep a rectangle, in pixels
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    ///Clockwise, by quarter turns
+    Rotate {
+        degrees: i32,
+    },
+    Flip {
+        axis: FlipAxis,
+    },
+    ///From -100 to 100
+    Brightness {
+        value: i32,
+    },
+    ///In stops, from -5 to 5
+    Exposure {
+        stops: f64,
+    },
+}
+
+impl EditOperation {
+    fn validate(&self) -> Result<(), ValidationError> {
+        let valid = match *self {
+            Self::Crop { width, height, .. } => width > 0 && height > 0,
+            Self::Rotate { degrees } => degrees % 90 == 0,
+            Self::Flip { .. } => true,
+            Self::Brightness { value } => (-BRIGHTNESS_MAX..=BRIGHTNESS_MAX).contains(&value),
+            Self::Exposure { stops } => stops.abs() <= EXPOSURE_MAX_STOPS,
+        };
+        if !valid {
+            return Err(ValidationError::InvalidEdit(format!("{:?}", self)));
+        }
+        Ok(())
+    }
+}
+
+///Operations replacing the current edits of a resource
+#[derive(Deserialize, Debug)]
+pub struct EditReq {
+    pub operations: Vec<EditOperation>,
+}
+
+impl EditReq {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.operations.len() > EDITS_MAX {
+            return Err(ValidationError::TooManyEdits(EDITS_MAX));
+        }
+        self.operations.iter().try_for_each(EditOperation::validate)
+    }
+}
+
+///Edits of a resource as they were at some point
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EditVersion {
+    ///Increasing, 0 being the original
+    pub number: u32,
+    pub operations: Vec<EditOperation>,
+    pub created_at: DateTime,
+    pub author: ObjectId,
+}
+
+impl EditVersion {
+    pub fn new(number: u32, operations: Vec<EditOperation>, author: ObjectId) -> Self {
+        Self {
+            number,
+            operations,
+            created_at: Utc::now().into(),
+            author,
+        }
+    }
+}
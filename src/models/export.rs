@@ -0,0 +1,101 @@
+use chrono::Utc;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use super::User;
+
+///Pending exports older than this are considered abandoned
+pub const EXPORT_TIMEOUT_HOURS: i64 = 24;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+///Archive of every media of a user along with a manifest of their metadata
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Export<StorageType> {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    owner: ObjectId,
+    status: ExportStatus,
+    created_at: DateTime,
+    finished_at: Option<DateTime>,
+    storage: Option<StorageType>,
+    ///Size of the archive in bytes
+    #[serde(default)]
+    size: i64,
+    ///Number of media in the archive
+    #[serde(default)]
+    media: i64,
+}
+
+///Export as shown to its owner
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportInfo {
+    id: Option<ObjectId>,
+    status: ExportStatus,
+    created_at: DateTime,
+    finished_at: Option<DateTime>,
+    size: i64,
+    media: i64,
+    ///Link to the archive once ready
+    download: Option<String>,
+}
+
+impl<StorageType> Export<StorageType> {
+    pub fn new(user: &User) -> Self {
+        Self {
+            id: None,
+            owner: user.get_id().unwrap(),
+            status: ExportStatus::Pending,
+            created_at: Utc::now().into(),
+            finished_at: None,
+            storage: None,
+            size: 0,
+            media: 0,
+        }
+    }
+
+    pub fn get_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    pub fn get_owner(&self) -> &ObjectId {
+        &self.owner
+    }
+
+    pub fn get_status(&self) -> ExportStatus {
+        self.status
+    }
+
+    pub fn get_storage(&self) -> Option<&StorageType> {
+        self.storage.as_ref()
+    }
+
+    pub fn download_url(&self, public_url: &str) -> Option<String> {
+        match (self.status, &self.id) {
+            (ExportStatus::Ready, Some(id)) => {
+                Some(format!("{}/user/exports/{}", public_url, id.to_hex()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn info(&self, public_url: &str) -> ExportInfo {
+        ExportInfo {
+            id: self.id.clone(),
+            status: self.status,
+            created_at: self.created_at,
+            finished_at: self.finished_at,
+            size: self.size,
+            media: self.media,
+            download: self.download_url(public_url),
+        }
+    }
+}
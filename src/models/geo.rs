@@ -0,0 +1,101 @@
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_ZOOM: u32 = 22;
+
+fn valid_lng(lng: f64) -> bool {
+    (-180.0..=180.0).contains(&lng)
+}
+
+fn valid_lat(lat: f64) -> bool {
+    (-90.0..=90.0).contains(&lat)
+}
+
+///GeoJSON point
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeoPoint {
+    #[serde(rename = "type")]
+    kind: String,
+    ///Longitude then latitude, as ordered by GeoJSON
+    coordinates: [f64; 2],
+}
+
+impl GeoPoint {
+    ///None if coordinates are out of range
+    pub fn new(lng: f64, lat: f64) -> Option<Self> {
+        if !valid_lng(lng) || !valid_lat(lat) {
+            return None;
+        }
+        Some(Self {
+            kind: "Point".to_string(),
+            coordinates: [lng, lat],
+        })
+    }
+
+    pub fn to_bson(&self) -> Bson {
+        Bson::Document(
+            doc! {"type": "Point", "coordinates": [self.coordinates[0], self.coordinates[1]]},
+        )
+    }
+}
+
+///Area between two longitudes and two latitudes.
+///West can be greater than east for boxes crossing the antimeridian
+#[derive(Deserialize, Debug, Clone)]
+pub struct BoundingBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl BoundingBox {
+    pub fn is_valid(&self) -> bool {
+        valid_lng(self.west)
+            && valid_lng(self.east)
+            && valid_lat(self.south)
+            && valid_lat(self.north)
+            && self.south < self.north
+            && self.west != self.east
+    }
+
+    ///Query matching locations in the box. Coordinates are compared as they are,
+    ///so that edges follow meridians and parallels as on a map
+    pub fn to_filter(&self) -> Document {
+        let lng = if self.west <= self.east {
+            doc! {"$gte": self.west, "$lte": self.east}
+        } else {
+            //Crossing the antimeridian, anything but the gap between east and west
+            doc! {"$not": {"$gt": self.east, "$lt": self.west}}
+        };
+        doc! {
+            "location.coordinates.0": lng,
+            "location.coordinates.1": {"$gte": self.south, "$lte": self.north},
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RadiusQuery {
+    pub lng: f64,
+    pub lat: f64,
+    ///In meters
+    pub radius: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ClusterQuery {
+    pub zoom: u32,
+}
+
+///Media of a web mercator tile at a zoom level
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeoCluster {
+    pub x: i64,
+    pub y: i64,
+    pub count: i64,
+    ///Average position of the media, longitude then latitude
+    pub center: [f64; 2],
+    ///One of the media, to be used as a thumbnail
+    pub sample: ObjectId,
+}
@@ -0,0 +1,46 @@
+use chrono::Utc;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use super::User;
+
+const CODE_LEN: usize = 16;
+
+///Single use code allowing registration when it is invite only
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Invite {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    code: String,
+    created_by: ObjectId,
+    created_at: DateTime,
+    used_by: Option<ObjectId>,
+    used_at: Option<DateTime>,
+}
+
+impl Invite {
+    pub fn new(creator: &User) -> Self {
+        let mut code = [0u8; CODE_LEN];
+        SystemRandom::new()
+            .fill(&mut code)
+            .expect("Cannot generate invite code");
+        Self {
+            id: None,
+            code: hex::encode(code),
+            created_by: creator.get_id().unwrap(),
+            created_at: Utc::now().into(),
+            used_by: None,
+            used_at: None,
+        }
+    }
+
+    pub fn get_code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn get_creator(&self) -> &ObjectId {
+        &self.created_by
+    }
+}
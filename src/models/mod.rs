@@ -1,5 +1,18 @@
+mod batch;
+mod blob;
+mod edit;
+mod export;
+mod geo;
+mod invite;
+mod password_reset;
 mod resource;
 mod session;
+mod takeout;
+mod upload;
 mod user;
+mod watermark;
 
-pub use self::{resource::*, session::*, user::*};
+pub use self::{
+    batch::*, blob::*, edit::*, export::*, geo::*, invite::*, password_reset::*, resource::*,
+    session::*, takeout::*, upload::*, user::*, watermark::*,
+};
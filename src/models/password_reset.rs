@@ -0,0 +1,55 @@
+use chrono::{Duration, Utc};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use super::User;
+
+const TOKEN_LEN: usize = 32;
+pub const TOKEN_VALIDITY_MINUTES: i64 = 30;
+
+///Pending password reset, only a digest of the token is stored
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordReset {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    user: ObjectId,
+    #[serde(with = "serde_bytes")]
+    token_hash: Vec<u8>,
+    expires_at: DateTime,
+}
+
+impl PasswordReset {
+    ///Create a reset for user, returns it along with the clear token to send
+    pub fn new(user: &User) -> (Self, String) {
+        let mut token = [0u8; TOKEN_LEN];
+        SystemRandom::new()
+            .fill(&mut token)
+            .expect("Cannot generate reset token");
+        let token = hex::encode(token);
+        let reset = Self {
+            id: None,
+            user: user.get_id().unwrap(),
+            token_hash: Self::hash_token(&token),
+            expires_at: (Utc::now() + Duration::minutes(TOKEN_VALIDITY_MINUTES)).into(),
+        };
+        (reset, token)
+    }
+
+    pub fn hash_token(token: &str) -> Vec<u8> {
+        digest::digest(&digest::SHA256, token.as_bytes())
+            .as_ref()
+            .to_vec()
+    }
+
+    pub fn get_user(&self) -> &ObjectId {
+        &self.user
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.0 < Utc::now()
+    }
+}
@@ -1,12 +1,16 @@
-use crate::{models::User, tools::ResourceIOError};
+use crate::{
+    models::{EditOperation, EditVersion, GeoPoint, User, VERSIONS_MAX},
+    tools::{ResourceIOError, ValidationError},
+};
 use actix_multipart::Field;
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::Utc;
 use futures::Stream;
 use mime::Mime;
-use mongodb::bson::{doc, oid::ObjectId, Bson};
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::{fmt::Debug, pin::Pin};
+use std::{fmt::Debug, path::Path, pin::Pin};
 
 extern crate std;
 
@@ -26,9 +30,23 @@ mod internal {
 
 pub type Dim = internal::Dimension;
 
+pub const TITLE_MAX_LEN: usize = 200;
+pub const DESCRIPTION_MAX_LEN: usize = 5000;
+pub const TAGS_MAX: usize = 50;
+pub const TAG_MAX_LEN: usize = 50;
+
+///Tags are compared case insensitively, keep a single spelling. None for blank tags
+pub fn normalize_tag(tag: &str) -> Result<Option<String>, ValidationError> {
+    let tag = tag.trim().to_lowercase();
+    if tag.chars().count() > TAG_MAX_LEN {
+        return Err(ValidationError::TagLength(TAG_MAX_LEN));
+    }
+    Ok(Some(tag).filter(|t| !t.is_empty()))
+}
+
 pub trait Media {
     fn get_dim(&self) -> Dim;
-    fn get_size(&self) -> i32;
+    fn get_size(&self) -> i64;
     fn get_owner(&self) -> ObjectId;
     fn get_extension(&self) -> &Mime;
 }
@@ -50,12 +68,17 @@ pub type BytesStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send
 #[async_trait]
 pub trait Readable {
     async fn read(&self) -> BytesStream;
+    ///Stream of bytes start to end included
+    async fn read_range(&self, start: u64, end: u64) -> BytesStream;
 }
 
 #[async_trait]
 pub trait Writable {
     async fn save(&self, data: Vec<u8>) -> ();
+    ///Save the content of the file at path without loading it in memory
+    async fn save_file(&self, path: &Path) -> std::io::Result<()>;
     async fn alloc() -> Self;
+    async fn delete(&self) -> std::io::Result<()>;
 }
 
 pub trait Identifiable {
@@ -71,6 +94,86 @@ pub struct AccessRight {
     write: bool,
 }
 
+///Fields of a resource editable by users, an empty string clears a field
+#[derive(Deserialize, Debug)]
+pub struct MediaUpdateReq {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    ///Replaces every tag of the resource
+    pub tags: Option<Vec<String>>,
+}
+
+///Camera settings read from the EXIF data of an image
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ExifInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub make: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lens: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<DateTime>,
+    ///As displayed by cameras, e.g. 1/250
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub f_number: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iso: Option<i32>,
+    ///In millimeters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_length: Option<f64>,
+}
+
+///Properties of a video read from its container
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct VideoInfo {
+    ///mp4, quicktime, webm or matroska
+    pub container: String,
+    ///In seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    ///As displayed, rotation applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_codec: Option<String>,
+}
+
+impl VideoInfo {
+    pub fn mime(&self) -> Mime {
+        match self.container.as_str() {
+            "matroska" => "video/x-matroska",
+            "quicktime" => "video/quicktime",
+            "webm" => "video/webm",
+            _ => "video/mp4",
+        }
+        .parse()
+        .unwrap()
+    }
+}
+
+///Properties of an animated GIF, PNG or WebP
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AnimationInfo {
+    pub frames: u32,
+    ///Of a single loop, in seconds
+    pub duration: f64,
+}
+
+///Data as shown to other users, with the watermark of the owner drawn over it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Watermarked<StorageType> {
+    pub storage: StorageType,
+    ///Digest of what was drawn, the copy is outdated once it changes
+    pub key: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Resource<StorageType>
 where
@@ -88,6 +191,61 @@ where
     access: Vec<AccessRight>,
     r_public: bool,
     w_public: bool,
+    ///Size in bytes of the stored data
+    #[serde(default)]
+    size: i64,
+    ///Hex SHA-256 of the stored data, shared with the Blob holding the storage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    ///Perceptual hash of images, bits of a u64 stored as i64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    phash: Option<i64>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    ///Sanitized name of the file as it was uploaded
+    #[serde(default)]
+    filename: Option<String>,
+    #[serde(default)]
+    uploaded_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exif: Option<ExifInfo>,
+    ///Where the media was taken, missing rather than null to stay out of the 2dsphere index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<GeoPoint>,
+    ///Set while the resource is in the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    video: Option<VideoInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    animation: Option<AnimationInfo>,
+    ///Still image shown in place of a video or an animation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poster: Option<StorageType>,
+    ///Smaller animated WebP of a large animation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preview: Option<StorageType>,
+    ///Applied on display, the stored data stays untouched
+    #[serde(default)]
+    edits: Vec<EditOperation>,
+    #[serde(default)]
+    versions: Vec<EditVersion>,
+    ///Compact blurred preview of the image or poster, see https://blurha.sh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blurhash: Option<String>,
+    ///Main colours as #rrggbb, most present first
+    #[serde(default)]
+    palette: Vec<String>,
+    ///EXIF orientation of the stored data, from 1 for upright to 8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    orientation: Option<u32>,
+    ///Data turned upright with edits applied, null when it would match the stored data
+    rendered: Option<StorageType>,
+    watermarked: Option<Watermarked<StorageType>>,
 }
 
 fn serialize_mime<S>(element: &Mime, serializer: S) -> Result<S::Ok, S::Error>
@@ -115,17 +273,23 @@ where
 
     ///Get a stream of underlying storage
     pub async fn read(&self, request_user: Option<&User>) -> Result<BytesStream, ResourceIOError> {
-        if self.r_public {
+        if self.can_read(request_user) {
             return Ok(self._storage.as_ref().unwrap().read().await);
         }
-        if let Some(user) = request_user {
-            if let Some(request_id) = user.get_id() {
-                if request_id == self.get_owner()
-                    || self.access.iter().any(|a| a.user == request_id)
-                {
-                    return Ok(self._storage.as_ref().unwrap().read().await);
-                }
-            }
+        Err(ResourceIOError::InsufficientPermissions(
+            "reading".to_string(),
+        ))
+    }
+
+    ///Get a stream of bytes start to end included of underlying storage
+    pub async fn read_range(
+        &self,
+        request_user: Option<&User>,
+        start: u64,
+        end: u64,
+    ) -> Result<BytesStream, ResourceIOError> {
+        if self.can_read(request_user) {
+            return Ok(self._storage.as_ref().unwrap().read_range(start, end).await);
         }
         Err(ResourceIOError::InsufficientPermissions(
             "reading".to_string(),
@@ -155,6 +319,47 @@ where
         ))
     }
 
+    ///Save the file at path to storage of resource
+    pub async fn save_file(
+        &self,
+        request_user: Option<&User>,
+        path: &Path,
+    ) -> Result<(), ResourceIOError> {
+        if self.can_write(request_user) {
+            self._storage.as_ref().unwrap().save_file(path).await?;
+            return Ok(());
+        }
+        Err(ResourceIOError::InsufficientPermissions(
+            "writing".to_string(),
+        ))
+    }
+
+    ///Whether the owner or an admin is requesting
+    pub fn can_delete(&self, request_user: Option<&User>) -> bool {
+        match request_user {
+            Some(user) => user.is_admin() || user.get_id() == Some(self.get_owner()),
+            None => false,
+        }
+    }
+
+    ///Whether user can change who accesses the resource, only the owner or an admin can do it
+    pub fn can_share(&self, request_user: Option<&User>) -> bool {
+        self.can_delete(request_user)
+    }
+
+    ///Delete underlying storage, only the owner or an admin can do it
+    pub async fn delete(&self, request_user: Option<&User>) -> Result<(), ResourceIOError> {
+        if self.can_delete(request_user) {
+            if let Some(storage) = self._storage.as_ref() {
+                storage.delete().await?;
+            }
+            return Ok(());
+        }
+        Err(ResourceIOError::InsufficientPermissions(
+            "deleting".to_string(),
+        ))
+    }
+
     ///Allocate storage of underlying storage.
     ///Calls alloc() of Storage
     pub async fn alloc(&mut self) {
@@ -166,6 +371,16 @@ where
         &self._storage
     }
 
+    ///Detach underlying storage, before pointing to other data
+    pub fn take_storage(&mut self) -> Option<StorageType> {
+        self._storage.take()
+    }
+
+    ///Use an already written storage instead of allocating one
+    pub fn set_storage(&mut self, storage: StorageType) {
+        self._storage = Some(storage);
+    }
+
     ///Change access rights of the resource
     pub fn update_public_access(&mut self, r_public: Option<bool>, w_public: Option<bool>) {
         self.r_public = match r_public {
@@ -181,6 +396,19 @@ where
 
     ///Create resource from http body Field
     pub fn from_field(field: &Field, user: &User) -> Self {
+        let mut res = Self::new(field.content_type().clone(), user);
+        if let Some(filename) = field
+            .content_disposition()
+            .as_ref()
+            .and_then(|d| d.get_filename())
+        {
+            res.set_filename(filename);
+        }
+        res
+    }
+
+    ///Create resource of user holding data of type extension
+    pub fn new(extension: Mime, user: &User) -> Self {
         let id = user.get_id().unwrap();
         Self {
             id: None,
@@ -190,9 +418,272 @@ where
                 user: id.clone(),
                 write: true,
             }],
-            extension: field.content_type().clone(),
+            extension,
             r_public: false,
             w_public: false,
+            size: 0,
+            sha256: None,
+            phash: None,
+            title: None,
+            description: None,
+            tags: Vec::new(),
+            filename: None,
+            uploaded_at: Some(Utc::now().into()),
+            exif: None,
+            location: None,
+            deleted_at: None,
+            video: None,
+            animation: None,
+            poster: None,
+            preview: None,
+            edits: Vec::new(),
+            versions: Vec::new(),
+            blurhash: None,
+            palette: Vec::new(),
+            orientation: None,
+            rendered: None,
+            watermarked: None,
+        }
+    }
+
+    ///Keep the name a client gave to the file, stripped of path separators and reserved characters
+    pub fn set_filename(&mut self, filename: &str) {
+        let filename = sanitize_filename::sanitize(filename);
+        self.filename = Some(filename).filter(|f| !f.is_empty());
+    }
+
+    ///Apply user edits, refusing fields that are too long
+    pub fn update_metadata(&mut self, req: &MediaUpdateReq) -> Result<(), ValidationError> {
+        if let Some(title) = &req.title {
+            let title = title.trim();
+            if title.chars().count() > TITLE_MAX_LEN {
+                return Err(ValidationError::TitleLength(TITLE_MAX_LEN));
+            }
+            self.title = Some(title.to_string()).filter(|t| !t.is_empty());
+        }
+        if let Some(description) = &req.description {
+            if description.chars().count() > DESCRIPTION_MAX_LEN {
+                return Err(ValidationError::DescriptionLength(DESCRIPTION_MAX_LEN));
+            }
+            self.description = Some(description.clone()).filter(|d| !d.trim().is_empty());
+        }
+        if let Some(tags) = &req.tags {
+            let mut normalized: Vec<String> = Vec::new();
+            for tag in tags.iter() {
+                if let Some(tag) = normalize_tag(tag)? {
+                    if !normalized.contains(&tag) {
+                        normalized.push(tag);
+                    }
+                }
+            }
+            if normalized.len() > TAGS_MAX {
+                return Err(ValidationError::TooManyTags(TAGS_MAX));
+            }
+            self.tags = normalized;
+        }
+        Ok(())
+    }
+
+    pub fn get_filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    ///When the media was captured, or uploaded if unknown
+    pub fn get_date(&self) -> Option<chrono::DateTime<Utc>> {
+        self.exif
+            .as_ref()
+            .and_then(|e| e.taken_at)
+            .or(self.uploaded_at)
+            .map(|d| d.0)
+    }
+
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    ///Set camera details read from the file, a capture date already known is kept
+    pub fn set_exif(&mut self, mut exif: ExifInfo) {
+        if let Some(taken_at) = self.exif.as_ref().and_then(|e| e.taken_at) {
+            exif.taken_at = Some(taken_at);
+        }
+        self.exif = Some(exif);
+    }
+
+    ///Capture date known apart from the file
+    pub fn set_taken_at(&mut self, taken_at: DateTime) {
+        self.exif.get_or_insert_with(ExifInfo::default).taken_at = Some(taken_at);
+    }
+
+    pub fn get_location(&self) -> Option<&GeoPoint> {
+        self.location.as_ref()
+    }
+
+    pub fn set_location(&mut self, location: GeoPoint) {
+        self.location = Some(location);
+    }
+
+    ///Type is corrected when the data turns out to be a video
+    pub fn set_video(&mut self, video: VideoInfo) {
+        if self.extension.type_() != mime::VIDEO {
+            self.extension = video.mime();
+        }
+        self.video = Some(video);
+    }
+
+    pub fn get_animation(&self) -> Option<&AnimationInfo> {
+        self.animation.as_ref()
+    }
+
+    pub fn set_animation(&mut self, animation: AnimationInfo) {
+        self.animation = Some(animation);
+    }
+
+    ///Type of the stored data once it was converted
+    pub fn set_extension(&mut self, extension: Mime) {
+        self.extension = extension;
+    }
+
+    pub fn get_poster(&self) -> Option<&StorageType> {
+        self.poster.as_ref()
+    }
+
+    pub fn set_poster(&mut self, poster: StorageType) {
+        self.poster = Some(poster);
+    }
+
+    pub fn get_preview(&self) -> Option<&StorageType> {
+        self.preview.as_ref()
+    }
+
+    pub fn set_preview(&mut self, preview: StorageType) {
+        self.preview = Some(preview);
+    }
+
+    pub fn get_edits(&self) -> &[EditOperation] {
+        &self.edits
+    }
+
+    pub fn get_versions(&self) -> &[EditVersion] {
+        &self.versions
+    }
+
+    pub fn find_version(&self, number: u32) -> Option<&EditVersion> {
+        self.versions.iter().find(|v| v.number == number)
+    }
+
+    ///Replace edits, recording them as a new version
+    pub fn set_edits(&mut self, operations: Vec<EditOperation>, author: ObjectId) {
+        if self.versions.is_empty() {
+            let mut original = EditVersion::new(0, Vec::new(), self.owner.clone());
+            original.created_at = self.uploaded_at.unwrap_or(original.created_at);
+            self.versions.push(original);
+        }
+        let number = self.versions.last().map_or(0, |v| v.number) + 1;
+        self.versions
+            .push(EditVersion::new(number, operations.clone(), author));
+        if self.versions.len() > VERSIONS_MAX {
+            self.versions.remove(1);
+        }
+        self.edits = operations;
+    }
+
+    ///Set what clients display while the media loads
+    pub fn set_placeholder(&mut self, blurhash: String, palette: Vec<String>) {
+        self.blurhash = Some(blurhash);
+        self.palette = palette;
+    }
+
+    pub fn set_orientation(&mut self, orientation: u32) {
+        self.orientation = Some(orientation);
+    }
+
+    pub fn get_orientation(&self) -> u32 {
+        self.orientation.unwrap_or(1)
+    }
+
+    ///Whether the stored data displays differently once its edits and orientation are applied
+    pub fn needs_rendering(&self) -> bool {
+        !self.edits.is_empty() || self.get_orientation() != 1
+    }
+
+    pub fn get_rendered(&self) -> Option<&StorageType> {
+        self.rendered.as_ref()
+    }
+
+    ///Set the data rendered from current edits, returns the previous one
+    pub fn replace_rendered(&mut self, rendered: Option<StorageType>) -> Option<StorageType> {
+        std::mem::replace(&mut self.rendered, rendered)
+    }
+
+    ///Type of rendered data, edits of images other than JPEG are rendered as PNG
+    pub fn rendered_mime(&self) -> Mime {
+        if self.extension == mime::IMAGE_JPEG {
+            mime::IMAGE_JPEG
+        } else {
+            mime::IMAGE_PNG
+        }
+    }
+
+    pub fn get_watermarked(&self) -> Option<&Watermarked<StorageType>> {
+        self.watermarked.as_ref()
+    }
+
+    ///Set the watermarked copy, returns the previous one
+    pub fn replace_watermarked(
+        &mut self,
+        watermarked: Option<Watermarked<StorageType>>,
+    ) -> Option<Watermarked<StorageType>> {
+        std::mem::replace(&mut self.watermarked, watermarked)
+    }
+
+    pub fn is_trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    pub fn set_size(&mut self, size: i64) {
+        self.size = size;
+    }
+
+    pub fn get_hash(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    pub fn set_hash(&mut self, sha256: String) {
+        self.sha256 = Some(sha256);
+    }
+
+    pub fn get_phash(&self) -> Option<u64> {
+        self.phash.map(|h| h as u64)
+    }
+
+    pub fn set_phash(&mut self, phash: u64) {
+        self.phash = Some(phash as i64);
+    }
+
+    ///Whether resource is publicly writable or user is its owner or was granted write access
+    pub fn can_write(&self, request_user: Option<&User>) -> bool {
+        if self.w_public {
+            return true;
+        }
+        match request_user.and_then(|u| u.get_id()) {
+            Some(request_id) => {
+                request_id == self.get_owner()
+                    || self.access.iter().any(|a| a.user == request_id && a.write)
+            }
+            None => false,
+        }
+    }
+
+    ///Whether resource is public or user is its owner or was granted access
+    pub fn can_read(&self, request_user: Option<&User>) -> bool {
+        if self.r_public {
+            return true;
+        }
+        match request_user.and_then(|u| u.get_id()) {
+            Some(request_id) => {
+                request_id == self.get_owner() || self.access.iter().any(|a| a.user == request_id)
+            }
+            None => false,
         }
     }
 }
@@ -205,8 +696,8 @@ where
         todo!()
     }
 
-    fn get_size(&self) -> i32 {
-        todo!()
+    fn get_size(&self) -> i64 {
+        self.size
     }
 
     fn get_owner(&self) -> ObjectId {
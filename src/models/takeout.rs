@@ -0,0 +1,56 @@
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TakeoutStatus {
+    Imported,
+    ///Imported, the user already stored identical data
+    Duplicate,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TakeoutItem {
+    ///Path of the media within the archive
+    pub file: String,
+    pub status: TakeoutStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+///Outcome of an archive import, media missing from items were not attempted
+#[derive(Serialize, Debug, Default)]
+pub struct TakeoutReport {
+    pub imported: usize,
+    pub failed: usize,
+    pub items: Vec<TakeoutItem>,
+}
+
+impl TakeoutReport {
+    pub fn push_imported(&mut self, file: String, id: ObjectId, duplicate: bool) {
+        self.imported += 1;
+        self.items.push(TakeoutItem {
+            file,
+            status: if duplicate {
+                TakeoutStatus::Duplicate
+            } else {
+                TakeoutStatus::Imported
+            },
+            id: Some(id),
+            error: None,
+        });
+    }
+
+    pub fn push_failed(&mut self, file: String, error: String) {
+        self.failed += 1;
+        self.items.push(TakeoutItem {
+            file,
+            status: TakeoutStatus::Failed,
+            id: None,
+            error: Some(error),
+        });
+    }
+}
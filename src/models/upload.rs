@@ -0,0 +1,67 @@
+use chrono::Utc;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use super::User;
+
+///Resumable upload whose bytes are staged on disk until all of them are received
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Upload {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    owner: ObjectId,
+    ///Total size announced when the upload was created
+    length: i64,
+    ///Bytes received so far
+    offset: i64,
+    mime: String,
+    filename: Option<String>,
+    created_at: DateTime,
+    ///Last time bytes were received, uploads left alone for long are dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<DateTime>,
+}
+
+impl Upload {
+    pub fn new(user: &User, length: i64, mime: String, filename: Option<String>) -> Self {
+        Self {
+            id: None,
+            owner: user.get_id().unwrap(),
+            length,
+            offset: 0,
+            mime,
+            filename,
+            created_at: Utc::now().into(),
+            updated_at: Some(Utc::now().into()),
+        }
+    }
+
+    pub fn get_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    pub fn get_owner(&self) -> &ObjectId {
+        &self.owner
+    }
+
+    pub fn get_length(&self) -> i64 {
+        self.length
+    }
+
+    pub fn get_offset(&self) -> i64 {
+        self.offset
+    }
+
+    pub fn get_mime(&self) -> &str {
+        &self.mime
+    }
+
+    pub fn get_filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
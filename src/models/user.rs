@@ -1,15 +1,22 @@
-use crate::tools::UserError;
+use crate::tools::{
+    validate_email, validate_password, validate_username, PasswordBlocklist, UserError,
+    ValidationError,
+};
 use actix_identity::Identity;
 use actix_web::{
-    dev::Payload, error::ErrorUnauthorized, web::Data, Error, FromRequest, HttpRequest,
+    dev::Payload,
+    error::{ErrorForbidden, ErrorUnauthorized},
+    web::Data,
+    Error, FromRequest, HttpRequest,
 };
+use chrono::Utc;
 use futures::Future;
-use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{oid::ObjectId, DateTime};
 use ring::{digest, pbkdf2};
 use serde::{Deserialize, Serialize};
 use std::{num::NonZeroU32, pin::Pin, sync::RwLock, u8};
 
-use super::Sessions;
+use super::{Sessions, Watermark};
 
 static PBKDF2_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
 const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
@@ -22,12 +29,92 @@ const PBKDF2_ITER: u32 = 100_000;
 pub struct UserReq {
     username: String,
     password: String,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    invite: Option<String>,
 }
 
 impl UserReq {
+    pub fn new(username: String, password: String) -> Self {
+        Self {
+            username,
+            password,
+            email: None,
+            invite: None,
+        }
+    }
+
     pub fn get_username(&self) -> String {
         self.username.clone()
     }
+
+    pub fn get_invite(&self) -> Option<&str> {
+        self.invite.as_deref()
+    }
+
+    ///Check the request against username, password and email policies
+    pub fn validate(&self, blocklist: &PasswordBlocklist) -> Result<(), ValidationError> {
+        validate_username(&self.username)?;
+        validate_password(&self.password, blocklist)?;
+        if let Some(email) = &self.email {
+            validate_email(email)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordChangeReq {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetReq {
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordResetConfirmReq {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDeletionReq {
+    pub password: String,
+    ///Username receiving the owned resources, they are deleted when missing
+    #[serde(default)]
+    pub transfer_to: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UserDisableReq {
+    pub disabled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserPasswordReq {
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct UserSearchReq {
+    #[serde(default)]
+    pub q: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -37,6 +124,75 @@ pub struct User {
     pub username: String,
     #[serde(with = "serde_bytes")]
     pub credential: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    ///Consecutive failed logins, reset on success
+    #[serde(default)]
+    pub failed_logins: i32,
+    #[serde(default)]
+    pub total_failed_logins: i32,
+    #[serde(default)]
+    pub last_failed_login: Option<DateTime>,
+    #[serde(default)]
+    pub last_failed_ip: Option<String>,
+    #[serde(default)]
+    pub locked_until: Option<DateTime>,
+    #[serde(default)]
+    pub role: Role,
+    ///Disabled accounts cannot login anymore
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub invited_by: Option<ObjectId>,
+    ///Bytes stored by the resources owned by the user
+    #[serde(default)]
+    pub storage_used: i64,
+    ///Drawn over images of the user read by others
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<Watermark>,
+}
+
+///Account of the logged in user along with its storage limit
+#[derive(Serialize)]
+pub struct Account {
+    #[serde(flatten)]
+    pub user: User,
+    pub storage_limit: Option<i64>,
+}
+
+///Public view of a user, without its credential
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    id: Option<ObjectId>,
+    username: String,
+    email: Option<String>,
+    role: Role,
+    disabled: bool,
+    invited_by: Option<ObjectId>,
+    storage_used: i64,
+    failed_logins: i32,
+    total_failed_logins: i32,
+    last_failed_login: Option<DateTime>,
+    locked_until: Option<DateTime>,
+}
+
+impl From<&User> for UserInfo {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.get_id(),
+            username: user.get_username(),
+            email: user.email.clone(),
+            role: user.role,
+            disabled: user.disabled,
+            invited_by: user.invited_by.clone(),
+            storage_used: user.storage_used,
+            failed_logins: user.failed_logins,
+            total_failed_logins: user.total_failed_logins,
+            last_failed_login: user.last_failed_login,
+            locked_until: user.locked_until,
+        }
+    }
 }
 
 impl User {
@@ -62,18 +218,47 @@ impl User {
         salt
     }
 
-    pub fn new(req: &UserReq) -> Self {
-        let salt = Self::salt(&req.username);
+    fn derive_credential(username: &str, password: &str) -> Vec<u8> {
+        let salt = Self::salt(username);
         let iter = NonZeroU32::new(PBKDF2_ITER).unwrap();
         let mut cred = [0u8; CREDENTIAL_LEN];
-        pbkdf2::derive(PBKDF2_ALG, iter, &salt, req.password.as_bytes(), &mut cred);
+        pbkdf2::derive(PBKDF2_ALG, iter, &salt, password.as_bytes(), &mut cred);
+        cred.to_vec()
+    }
+
+    pub fn new(req: &UserReq) -> Self {
         Self {
             id: None,
             username: req.username.clone(),
-            credential: cred.to_vec(),
+            credential: Self::derive_credential(&req.username, &req.password),
+            email: req.email.clone(),
+            failed_logins: 0,
+            total_failed_logins: 0,
+            last_failed_login: None,
+            last_failed_ip: None,
+            locked_until: None,
+            role: Role::User,
+            disabled: false,
+            invited_by: None,
+            storage_used: 0,
+            watermark: None,
         }
     }
 
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    ///Whether logins are refused after repeated failures
+    pub fn is_locked(&self) -> bool {
+        self.locked_until.is_some_and(|until| until.0 > Utc::now())
+    }
+
+    ///Replace the stored credential, the caller has to persist the user
+    pub fn set_password(&mut self, password: &str) {
+        self.credential = Self::derive_credential(&self.username, password);
+    }
+
     pub fn get_username(&self) -> String {
         self.username.clone()
     }
@@ -112,3 +297,23 @@ impl FromRequest for User {
         })
     }
 }
+
+///Session of a user holding the admin role
+pub struct Admin(pub User);
+
+impl FromRequest for Admin {
+    type Config = ();
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Admin, Error>>>>;
+
+    fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
+        let fut = User::from_request(req, pl);
+        Box::pin(async move {
+            let user = fut.await?;
+            if user.is_admin() {
+                return Ok(Admin(user));
+            }
+            Err(ErrorForbidden("forbidden"))
+        })
+    }
+}
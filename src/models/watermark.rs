@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::ValidationError;
+
+pub const WATERMARK_TEXT_MAX_LEN: usize = 100;
+
+///Corner, edge or center of the image the mark is drawn at
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum WatermarkPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    #[default]
+    BottomRight,
+}
+
+///What is drawn over images
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WatermarkMark {
+    Text {
+        text: String,
+    },
+    ///Id of an image owned by the same user, such as a logo
+    Image {
+        resource: String,
+    },
+}
+
+///Mark drawn over images of a user when they are read by someone else
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Watermark {
+    pub mark: WatermarkMark,
+    #[serde(default)]
+    pub position: WatermarkPosition,
+    ///From 0 for invisible to 1 for opaque
+    pub opacity: f64,
+    ///Width of the mark relative to the width of the image, from 0 to 1
+    pub scale: f64,
+}
+
+impl Watermark {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if !(self.opacity > 0.0 && self.opacity <= 1.0) {
+            return Err(ValidationError::InvalidWatermark(
+                "opacity must be above 0 and at most 1".to_string(),
+            ));
+        }
+        if !(self.scale > 0.0 && self.scale <= 1.0) {
+            return Err(ValidationError::InvalidWatermark(
+                "scale must be above 0 and at most 1".to_string(),
+            ));
+        }
+        if let WatermarkMark::Text { text } = &self.mark {
+            if text.trim().is_empty() || text.chars().count() > WATERMARK_TEXT_MAX_LEN {
+                return Err(ValidationError::InvalidWatermark(format!(
+                    "text must be between 1 and {} characters",
+                    WATERMARK_TEXT_MAX_LEN
+                )));
+            }
+        }
+        Ok(())
+    }
+}
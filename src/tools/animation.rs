@@ -0,0 +1,257 @@
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder, webp::WebPEncoder},
+    imageops::{self, FilterType},
+    AnimationDecoder, ColorType, DynamicImage, Frames, ImageDecoder, ImageFormat, RgbaImage,
+};
+use std::io::Cursor;
+
+use crate::models::AnimationInfo;
+
+///Longest side of the still thumbnail of an animation
+const THUMBNAIL_SIZE: u32 = 512;
+///Longest side of animated previews, smaller animations are their own preview
+const PREVIEW_SIZE: u32 = 320;
+///Later frames are left out of previews
+const PREVIEW_MAX_FRAMES: usize = 300;
+///Later frames are neither decoded nor counted when probing
+const PROBE_MAX_FRAMES: usize = 1000;
+///Largest area of an animation, larger ones are handled as still images
+const MAX_PIXELS: u64 = 4096 * 4096;
+///Largest side of a WebP image
+const WEBP_MAX_SIZE: u32 = 16384;
+///Longest frame duration of a WebP animation, in milliseconds
+const WEBP_MAX_DURATION: u32 = (1 << 24) - 1;
+
+///Frames of an animated GIF, PNG or WebP, None for other formats, still PNG or WebP
+///and animations larger than MAX_PIXELS
+fn frames(data: &[u8]) -> Option<Frames<'_>> {
+    match image::guess_format(data).ok()? {
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(Cursor::new(data)).ok()?;
+            within_limits(&decoder).then(|| decoder.into_frames())
+        }
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(Cursor::new(data)).ok()?;
+            (decoder.is_apng() && within_limits(&decoder)).then(|| decoder.apng().into_frames())
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(data)).ok()?;
+            (decoder.has_animation() && within_limits(&decoder)).then(|| decoder.into_frames())
+        }
+        _ => None,
+    }
+}
+
+///Whether every frame can be decoded in memory, as each one covers the whole canvas
+fn within_limits<'a>(decoder: &impl ImageDecoder<'a>) -> bool {
+    let (width, height) = decoder.dimensions();
+    width as u64 * height as u64 <= MAX_PIXELS
+}
+
+fn delay_ms(frame: &image::Frame) -> u32 {
+    let (numerator, denominator) = frame.delay().numer_denom_ms();
+    numerator / denominator.max(1)
+}
+
+///Size fitting within a square of side size, None when the image already fits
+fn fit(width: u32, height: u32, size: u32) -> Option<(u32, u32)> {
+    if width <= size && height <= size {
+        return None;
+    }
+    let ratio = size as f64 / width.max(height) as f64;
+    Some((
+        ((width as f64 * ratio).round() as u32).max(1),
+        ((height as f64 * ratio).round() as u32).max(1),
+    ))
+}
+
+///Frame count and duration of an animated image along with a thumbnail of its first frame,
+///counting up to PROBE_MAX_FRAMES frames.
+///None for still images, including animations of a single frame
+pub fn probe_animation(data: &[u8]) -> Option<(AnimationInfo, DynamicImage)> {
+    let mut first = None;
+    let mut count = 0u32;
+    let mut duration = 0u64;
+    for frame in frames(data)?.take(PROBE_MAX_FRAMES) {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(_) => break,
+        };
+        count += 1;
+        duration += delay_ms(&frame) as u64;
+        if first.is_none() {
+            first = Some(frame.into_buffer());
+        }
+    }
+    if count < 2 {
+        return None;
+    }
+    let first = first?;
+    let thumbnail = match fit(first.width(), first.height(), THUMBNAIL_SIZE) {
+        Some((width, height)) => imageops::resize(&first, width, height, FilterType::Triangle),
+        None => first,
+    };
+    Some((
+        AnimationInfo {
+            frames: count,
+            duration: duration as f64 / 1000.0,
+        },
+        DynamicImage::ImageRgba8(thumbnail),
+    ))
+}
+
+///Animated WebP built frame by frame, each frame holding only the area that changed
+///since the previous one
+struct WebPAnimation {
+    width: u32,
+    height: u32,
+    previous: Option<RgbaImage>,
+    frames: Vec<u8>,
+    ///Offset of the duration of the last frame, extended when a frame does not change anything
+    last_duration: Option<usize>,
+    alpha: bool,
+}
+
+fn push_u24(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes()[..3]);
+}
+
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+impl WebPAnimation {
+    fn new(width: u32, height: u32) -> Option<Self> {
+        if width == 0 || height == 0 || width > WEBP_MAX_SIZE || height > WEBP_MAX_SIZE {
+            return None;
+        }
+        Some(Self {
+            width,
+            height,
+            previous: None,
+            frames: Vec::new(),
+            last_duration: None,
+            alpha: false,
+        })
+    }
+
+    ///Bounds of pixels differing from the previous frame, x and y even as WebP requires
+    fn changed(&self, frame: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+        let previous = match &self.previous {
+            Some(previous) => previous,
+            None => return Some((0, 0, self.width, self.height)),
+        };
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y, pixel) in frame.enumerate_pixels() {
+            if previous.get_pixel(x, y) != pixel {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+        if min_x == u32::MAX {
+            return None;
+        }
+        let (x, y) = (min_x & !1, min_y & !1);
+        Some((x, y, max_x + 1 - x, max_y + 1 - y))
+    }
+
+    fn push(&mut self, frame: RgbaImage, delay: u32) -> Option<()> {
+        if frame.dimensions() != (self.width, self.height) {
+            return None;
+        }
+        let delay = delay.min(WEBP_MAX_DURATION);
+        let (x, y, width, height) = match self.changed(&frame) {
+            Some(bounds) => bounds,
+            None => {
+                //Same image, the previous frame is shown longer
+                let offset = self.last_duration?;
+                let mut duration = [0u8; 4];
+                duration[..3].copy_from_slice(&self.frames[offset..offset + 3]);
+                let duration = (u32::from_le_bytes(duration) + delay).min(WEBP_MAX_DURATION);
+                self.frames[offset..offset + 3].copy_from_slice(&duration.to_le_bytes()[..3]);
+                return Some(());
+            }
+        };
+        let area = imageops::crop_imm(&frame, x, y, width, height).to_image();
+        self.alpha |= area.pixels().any(|p| p[3] < u8::MAX);
+        let mut encoded = Vec::new();
+        WebPEncoder::new_lossless(&mut encoded)
+            .encode(&area, width, height, ColorType::Rgba8)
+            .ok()?;
+
+        let mut payload = Vec::with_capacity(16 + encoded.len());
+        push_u24(&mut payload, x / 2);
+        push_u24(&mut payload, y / 2);
+        push_u24(&mut payload, width - 1);
+        push_u24(&mut payload, height - 1);
+        self.last_duration = Some(self.frames.len() + 8 + payload.len());
+        push_u24(&mut payload, delay);
+        //Pixels replace the area rather than being blended, nothing is disposed
+        payload.push(0b10);
+        //Bitstream chunk following the RIFF header of the still image
+        payload.extend_from_slice(&encoded[12..]);
+        push_chunk(&mut self.frames, b"ANMF", &payload);
+        self.previous = Some(frame);
+        Some(())
+    }
+
+    ///RIFF container looping forever
+    fn finish(self) -> Vec<u8> {
+        let mut header = vec![if self.alpha { 0x12 } else { 0x02 }, 0, 0, 0];
+        push_u24(&mut header, self.width - 1);
+        push_u24(&mut header, self.height - 1);
+        let mut chunks = Vec::new();
+        push_chunk(&mut chunks, b"VP8X", &header);
+        push_chunk(&mut chunks, b"ANIM", &[0, 0, 0, 0, 0, 0]);
+        chunks.extend_from_slice(&self.frames);
+
+        let mut webp = Vec::with_capacity(12 + chunks.len());
+        webp.extend_from_slice(b"RIFF");
+        webp.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        webp.extend_from_slice(b"WEBP");
+        webp.extend_from_slice(&chunks);
+        webp
+    }
+}
+
+///Encode frames of the animation in data as a lossless animated WebP, scaled down to fit
+///within size when given. None when data is not an animation, cannot be decoded or already fits
+fn encode_webp(data: &[u8], size: Option<u32>, max_frames: usize) -> Option<Vec<u8>> {
+    let mut animation: Option<WebPAnimation> = None;
+    let mut scaled = None;
+    for frame in frames(data)?.take(max_frames) {
+        let frame = frame.ok()?;
+        let delay = delay_ms(&frame);
+        let mut buffer = frame.into_buffer();
+        if animation.is_none() {
+            if let Some(size) = size {
+                scaled = Some(fit(buffer.width(), buffer.height(), size)?);
+            }
+            let (width, height) = scaled.unwrap_or_else(|| buffer.dimensions());
+            animation = Some(WebPAnimation::new(width, height)?);
+        }
+        if let Some((width, height)) = scaled {
+            buffer = imageops::resize(&buffer, width, height, FilterType::Triangle);
+        }
+        animation.as_mut()?.push(buffer, delay)?;
+    }
+    animation.map(WebPAnimation::finish)
+}
+
+///Animation scaled down as an animated WebP, None when the animation already fits
+///within PREVIEW_SIZE or the preview would not be lighter
+pub fn animated_preview(data: &[u8]) -> Option<Vec<u8>> {
+    encode_webp(data, Some(PREVIEW_SIZE), PREVIEW_MAX_FRAMES).filter(|p| p.len() < data.len())
+}
+
+///Same animation as an animated WebP without any loss, None when data is not an animation
+pub fn convert_to_webp(data: &[u8]) -> Option<Vec<u8>> {
+    encode_webp(data, None, usize::MAX)
+}
@@ -1,10 +1,50 @@
 use actix_web::{dev::HttpResponseBuilder, http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
 use thiserror::Error;
 
+///JSON body of client errors
+#[derive(Serialize)]
+struct ErrorBody {
+    field: &'static str,
+    message: String,
+}
+
 #[derive(Error, Debug)]
 pub enum ResourceIOError {
     #[error("InsufficientPermissions: cannot {0} resource")]
     InsufficientPermissions(String),
+    #[error("NotFound: no such resource")]
+    NotFound,
+    #[error("QuotaExceeded: storage quota of user is exhausted")]
+    QuotaExceeded,
+    #[error("TooLarge: upload exceeds the maximum size")]
+    TooLarge,
+    #[error("InvalidUpload: {0}")]
+    InvalidUpload(String),
+    #[error("InvalidQuery: {0}")]
+    InvalidQuery(String),
+    #[error("InvalidMetadata: {0}")]
+    InvalidMetadata(#[from] ValidationError),
+    #[error("UnsupportedVersion: tus version is not supported")]
+    UnsupportedVersion,
+    #[error("UnsupportedMediaType: expected {0}")]
+    UnsupportedMediaType(String),
+    #[error("OffsetMismatch: upload offset does not match")]
+    OffsetMismatch,
+    #[error("ExportInProgress: an export is already being built")]
+    ExportInProgress,
+    #[error("EditConflict: resource was edited concurrently")]
+    EditConflict,
+    #[error("BlockedAddress: remote address is not public")]
+    BlockedAddress,
+    #[error("FetchFailed: {0}")]
+    FetchFailed(String),
+    #[error("NoSpaceSaved: converted data would not be smaller")]
+    NoSpaceSaved,
+    #[error("WatermarkUnavailable: image cannot be shown without the watermark of its owner")]
+    WatermarkUnavailable,
+    #[error("IoError: cannot access staged data")]
+    IoError(#[from] std::io::Error),
     #[error("DatabaseError: something went wrong with mongodb")]
     DatabaseError(#[from] mongodb::error::Error),
 }
@@ -14,11 +54,87 @@ impl ResponseError for ResourceIOError {
         match *self {
             Self::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::InsufficientPermissions(_) => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            Self::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::InvalidUpload(_) | Self::InvalidQuery(_) | Self::InvalidMetadata(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::UnsupportedVersion => StatusCode::PRECONDITION_FAILED,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::OffsetMismatch => StatusCode::CONFLICT,
+            Self::ExportInProgress => StatusCode::CONFLICT,
+            Self::EditConflict => StatusCode::CONFLICT,
+            Self::BlockedAddress => StatusCode::FORBIDDEN,
+            Self::FetchFailed(_) => StatusCode::BAD_GATEWAY,
+            Self::NoSpaceSaved => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::WatermarkUnavailable => StatusCode::FORBIDDEN,
+            Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponseBuilder::new(self.status_code()).finish()
+        match *self {
+            Self::InvalidMetadata(ref e) => {
+                HttpResponseBuilder::new(self.status_code()).json(ErrorBody {
+                    field: e.field(),
+                    message: e.to_string(),
+                })
+            }
+            _ => HttpResponseBuilder::new(self.status_code()).finish(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MailerError {
+    #[error("IoError: cannot write mail")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ValidationError {
+    #[error("username must be between {0} and {1} characters")]
+    UsernameLength(usize, usize),
+    #[error("username can only contain letters, digits, '.', '_' and '-'")]
+    UsernameCharset,
+    #[error("password must be between {0} and {1} characters")]
+    PasswordLength(usize, usize),
+    #[error("password appears in a list of breached passwords")]
+    PasswordBreached,
+    #[error("email address is invalid")]
+    InvalidEmail,
+    #[error("title cannot exceed {0} characters")]
+    TitleLength(usize),
+    #[error("description cannot exceed {0} characters")]
+    DescriptionLength(usize),
+    #[error("a tag cannot exceed {0} characters")]
+    TagLength(usize),
+    #[error("at most {0} tags are allowed")]
+    TooManyTags(usize),
+    #[error("at most {0} edit operations are allowed")]
+    TooManyEdits(usize),
+    #[error("edit operation is invalid: {0}")]
+    InvalidEdit(String),
+    #[error("watermark is invalid: {0}")]
+    InvalidWatermark(String),
+    #[error("resources cannot be transferred to the account being deleted")]
+    SelfTransfer,
+}
+
+impl ValidationError {
+    pub fn field(&self) -> &'static str {
+        match *self {
+            Self::UsernameLength(..) | Self::UsernameCharset => "username",
+            Self::PasswordLength(..) | Self::PasswordBreached => "password",
+            Self::InvalidEmail => "email",
+            Self::TitleLength(_) => "title",
+            Self::DescriptionLength(_) => "description",
+            Self::TagLength(_) | Self::TooManyTags(_) => "tags",
+            Self::TooManyEdits(_) | Self::InvalidEdit(_) => "operations",
+            Self::InvalidWatermark(_) => "watermark",
+            Self::SelfTransfer => "transferTo",
+        }
     }
 }
 
@@ -26,19 +142,71 @@ impl ResponseError for ResourceIOError {
 pub enum UserError {
     #[error("MismatchingCredential: cannot login")]
     MismatchingCredential,
+    #[error("InvalidResetToken: token is unknown or expired")]
+    InvalidResetToken,
+    #[error("UnknownUser: no such user")]
+    UnknownUser,
+    #[error("AccountDisabled: account was disabled by an administrator")]
+    AccountDisabled,
+    #[error("InvalidInput: {0}")]
+    InvalidInput(#[from] ValidationError),
+    #[error("UsernameTaken: username is already taken")]
+    UsernameTaken,
+    #[error("RegistrationClosed: registration is disabled")]
+    RegistrationClosed,
+    #[error("InvalidInvite: invite code is missing, unknown or already used")]
+    InvalidInvite,
+    #[error("InvitesDisabled: only administrators can invite")]
+    InvitesDisabled,
+    #[error("TooManyAttempts: retry in {0} seconds")]
+    TooManyAttempts(u64),
     #[error("DatabaseError: something went wrong with mongodb")]
     DatabaseError(#[from] mongodb::error::Error),
+    #[error("MailerError: cannot send mail")]
+    MailerError(#[from] MailerError),
+    #[error("ResourceError: cannot update user resources")]
+    ResourceError(#[from] ResourceIOError),
 }
 
 impl ResponseError for UserError {
     fn status_code(&self) -> StatusCode {
         match *self {
             Self::MismatchingCredential => StatusCode::UNAUTHORIZED,
+            Self::InvalidResetToken => StatusCode::BAD_REQUEST,
+            Self::UnknownUser => StatusCode::NOT_FOUND,
+            Self::AccountDisabled => StatusCode::FORBIDDEN,
+            Self::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            Self::UsernameTaken => StatusCode::CONFLICT,
+            Self::RegistrationClosed => StatusCode::FORBIDDEN,
+            Self::InvalidInvite => StatusCode::BAD_REQUEST,
+            Self::InvitesDisabled => StatusCode::FORBIDDEN,
+            Self::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
             Self::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::MailerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ResourceError(ref e) => e.status_code(),
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponseBuilder::new(self.status_code()).finish()
+        match *self {
+            Self::TooManyAttempts(secs) => HttpResponseBuilder::new(self.status_code())
+                .insert_header(("retry-after", secs.to_string()))
+                .finish(),
+            Self::InvalidInput(ref e) => {
+                HttpResponseBuilder::new(self.status_code()).json(ErrorBody {
+                    field: e.field(),
+                    message: e.to_string(),
+                })
+            }
+            Self::UsernameTaken => HttpResponseBuilder::new(self.status_code()).json(ErrorBody {
+                field: "username",
+                message: "username is already taken".to_string(),
+            }),
+            Self::InvalidInvite => HttpResponseBuilder::new(self.status_code()).json(ErrorBody {
+                field: "invite",
+                message: "invite code is missing, unknown or already used".to_string(),
+            }),
+            _ => HttpResponseBuilder::new(self.status_code()).finish(),
+        }
     }
 }
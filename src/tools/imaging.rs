@@ -0,0 +1,184 @@
+use image::{imageops::FilterType, DynamicImage, ImageOutputFormat, ImageResult};
+use std::io::Cursor;
+
+use crate::{
+    models::{EditOperation, FlipAxis},
+    tools::ValidationError,
+};
+
+const JPEG_QUALITY: u8 = 90;
+///Approximation of the sRGB transfer curve
+const GAMMA: f64 = 2.2;
+
+///Difference hash: each bit tells whether a pixel is brighter than its right neighbour
+///on a 9x8 grayscale version of the image, so that resized or recompressed copies
+///get the same or a close hash
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+///Group hashes whose distance to another member is at most max_distance,
+///returns the indexes of every group of more than one element
+pub fn group_similar(hashes: &[u64], max_distance: u32) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..hashes.len() {
+        for j in (i + 1)..hashes.len() {
+            if hamming_distance(hashes[i], hashes[j]) <= max_distance {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = vec![Vec::new(); hashes.len()];
+    for i in 0..hashes.len() {
+        let r = root(&mut parent, i);
+        groups[r].push(i);
+    }
+    groups.into_iter().filter(|g| g.len() > 1).collect()
+}
+
+///Turn an image stored with an EXIF orientation so that it displays upright
+pub fn orient(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+///Scale light of every pixel by 2^stops, in linear light
+fn expose(img: DynamicImage, stops: f64) -> DynamicImage {
+    let factor = 2f64.powf(stops);
+    let table: Vec<u8> = (0..=255u8)
+        .map(|v| {
+            let linear = (v as f64 / 255.0).powf(GAMMA) * factor;
+            (linear.min(1.0).powf(1.0 / GAMMA) * 255.0).round() as u8
+        })
+        .collect();
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for channel in pixel.0.iter_mut().take(3) {
+            *channel = table[*channel as usize];
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+///Apply operations in order, failing when a crop exceeds the image it applies to
+pub fn apply_edits(
+    mut img: DynamicImage,
+    operations: &[EditOperation],
+) -> Result<DynamicImage, ValidationError> {
+    for operation in operations.iter() {
+        img = match *operation {
+            EditOperation::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if x as u64 + width as u64 > img.width() as u64
+                    || y as u64 + height as u64 > img.height() as u64
+                {
+                    return Err(ValidationError::InvalidEdit(format!(
+                        "crop exceeds the {}x{} image",
+                        img.width(),
+                        img.height()
+                    )));
+                }
+                img.crop_imm(x, y, width, height)
+            }
+            EditOperation::Rotate { degrees } => match degrees.rem_euclid(360) {
+                90 => img.rotate90(),
+                180 => img.rotate180(),
+                270 => img.rotate270(),
+                _ => img,
+            },
+            EditOperation::Flip {
+                axis: FlipAxis::Horizontal,
+            } => img.fliph(),
+            EditOperation::Flip {
+                axis: FlipAxis::Vertical,
+            } => img.flipv(),
+            EditOperation::Brightness { value } => img.brighten(value * 255 / 100),
+            EditOperation::Exposure { stops } => expose(img, stops),
+        };
+    }
+    Ok(img)
+}
+
+///Image turned upright according to its EXIF orientation with operations applied,
+///encoded without metadata so that no orientation tag remains
+pub fn render_image(
+    data: &[u8],
+    orientation: u32,
+    operations: &[EditOperation],
+    jpeg: bool,
+) -> Result<Vec<u8>, ValidationError> {
+    let img = image::load_from_memory(data)
+        .map_err(|_| ValidationError::InvalidEdit("image cannot be decoded".to_string()))?;
+    let img = apply_edits(orient(img, orientation), operations)?;
+    encode_image(&img, jpeg)
+        .map_err(|_| ValidationError::InvalidEdit("image cannot be encoded".to_string()))
+}
+
+///Encode as JPEG, which cannot hold transparency, or as PNG
+pub fn encode_image(img: &DynamicImage, jpeg: bool) -> ImageResult<Vec<u8>> {
+    let mut data = Cursor::new(Vec::new());
+    match img {
+        _ if jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_to(&mut data, ImageOutputFormat::Jpeg(JPEG_QUALITY))?,
+        //PNG holds at most 16 bits per channel
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            DynamicImage::ImageRgba16(img.to_rgba16())
+                .write_to(&mut data, ImageOutputFormat::Png)?
+        }
+        _ => img.write_to(&mut data, ImageOutputFormat::Png)?,
+    }
+    Ok(data.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba32FImage};
+
+    #[test]
+    fn float_images_are_encoded() {
+        let img = DynamicImage::ImageRgba32F(Rgba32FImage::new(4, 3));
+        let png = encode_image(&img, false).unwrap();
+        assert_eq!(image::guess_format(&png).unwrap(), ImageFormat::Png);
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (4, 3));
+        let jpeg = encode_image(&img, true).unwrap();
+        assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+    }
+}
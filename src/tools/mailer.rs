@@ -0,0 +1,66 @@
+use async_trait::async_trait;
+use std::{path::PathBuf, sync::Arc};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::config::MailerKind;
+use crate::tools::MailerError;
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<(), MailerError>;
+}
+
+///Mailer printing every mail on stdout
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailerError> {
+        println!(
+            "Mail to {}\nSubject: {}\n\n{}",
+            mail.to, mail.subject, mail.body
+        );
+        Ok(())
+    }
+}
+
+///Mailer appending every mail to a file, useful for tests
+pub struct FileMailer {
+    path: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(path: PathBuf) -> Self {
+        FileMailer { path }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailerError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}\n\n",
+            mail.to, mail.subject, mail.body
+        );
+        file.write_all(content.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+pub fn new_mailer(kind: &MailerKind) -> Arc<dyn Mailer> {
+    match kind {
+        MailerKind::Log => Arc::new(LogMailer),
+        MailerKind::File(path) => Arc::new(FileMailer::new(path.clone())),
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::{Duration, TimeZone, Utc};
+use exif::{Exif, Field, In, Reader, Tag, Value};
+use std::io::Cursor;
+
+use crate::models::{ExifInfo, GeoPoint};
+
+fn primary_field(exif: &Exif, tag: Tag) -> Option<&Field> {
+    exif.get_field(tag, In::PRIMARY)
+}
+
+fn ascii(field: &Field) -> Option<&[u8]> {
+    match &field.value {
+        Value::Ascii(values) => values.first().map(|v| v.as_slice()),
+        _ => None,
+    }
+}
+
+fn text(exif: &Exif, tag: Tag) -> Option<String> {
+    primary_field(exif, tag)
+        .and_then(ascii)
+        .map(|v| String::from_utf8_lossy(v).trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &primary_field(exif, tag)?.value {
+        Value::Rational(values) => values.first().map(|r| r.to_f64()),
+        _ => None,
+    }
+    .filter(|v| v.is_finite())
+}
+
+///Capture time, considered UTC unless the camera recorded an offset
+fn taken_at(exif: &Exif) -> Option<chrono::DateTime<Utc>> {
+    let dt =
+        exif::DateTime::from_ascii(primary_field(exif, Tag::DateTimeOriginal).and_then(ascii)?)
+            .ok()?;
+    let time = Utc
+        .ymd_opt(dt.year as i32, dt.month as u32, dt.day as u32)
+        .single()?
+        .and_hms_opt(dt.hour as u32, dt.minute as u32, dt.second as u32)?;
+    Some(time - Duration::minutes(dt.offset.unwrap_or(0) as i64))
+}
+
+///Degrees, minutes and seconds of a GPS coordinate, negated when its reference is `negative`
+fn gps_degrees(exif: &Exif, tag: Tag, ref_tag: Tag, negative: &[u8]) -> Option<f64> {
+    let degrees = match &primary_field(exif, tag)?.value {
+        Value::Rational(v) if v.len() >= 3 => {
+            v[0].to_f64() + v[1].to_f64() / 60.0 + v[2].to_f64() / 3600.0
+        }
+        _ => return None,
+    };
+    let sign = match primary_field(exif, ref_tag).and_then(ascii) {
+        Some(r) if r == negative => -1.0,
+        _ => 1.0,
+    };
+    Some(sign * degrees).filter(|d| d.is_finite())
+}
+
+///EXIF data embedded in an image
+pub fn read_exif(data: &[u8]) -> Option<Exif> {
+    Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+}
+
+///Camera fields of EXIF data, None if there is none
+pub fn camera_info(exif: &Exif) -> Option<ExifInfo> {
+    let info = ExifInfo {
+        make: text(exif, Tag::Make),
+        model: text(exif, Tag::Model),
+        lens: text(exif, Tag::LensModel),
+        taken_at: taken_at(exif).map(Into::into),
+        exposure_time: primary_field(exif, Tag::ExposureTime)
+            .map(|f| f.display_value().to_string()),
+        f_number: rational(exif, Tag::FNumber),
+        iso: primary_field(exif, Tag::PhotographicSensitivity)
+            .and_then(|f| f.value.get_uint(0))
+            .map(|v| v as i32),
+        focal_length: rational(exif, Tag::FocalLength),
+    };
+    Some(info).filter(|i| *i != ExifInfo::default())
+}
+
+///Where the image was taken. Cameras without a fix often write 0,0 which is ignored
+pub fn gps_location(exif: &Exif) -> Option<GeoPoint> {
+    let lat = gps_degrees(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b"S")?;
+    let lng = gps_degrees(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b"W")?;
+    if lat == 0.0 && lng == 0.0 {
+        return None;
+    }
+    GeoPoint::new(lng, lat)
+}
+
+///Orientation tag, from 1 for upright to 8
+pub fn exif_orientation(exif: &Exif) -> Option<u32> {
+    primary_field(exif, Tag::Orientation)?
+        .value
+        .get_uint(0)
+        .filter(|o| (1..=8).contains(o))
+}
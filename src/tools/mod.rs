@@ -1,6 +1,23 @@
+mod animation;
 mod error;
+mod imaging;
+mod logger;
+mod mailer;
+mod metadata;
+mod placeholder;
+mod remote;
 mod seaweed;
 mod seaweed_client;
 mod stream;
+mod takeout;
+mod throttle;
+mod validation;
+mod video;
+mod watermark;
+mod zip;
 
-pub use self::{error::*, seaweed::*, seaweed_client::*, stream::*};
+pub use self::{
+    animation::*, error::*, imaging::*, logger::*, mailer::*, metadata::*, placeholder::*,
+    remote::*, seaweed::*, seaweed_client::*, stream::*, takeout::*, throttle::*, validation::*,
+    video::*, watermark::*, zip::*,
+};
@@ -0,0 +1,157 @@
+use image::{imageops::FilterType, DynamicImage, RgbImage};
+
+const BASE83: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+///Images are reduced to this size before being summarized, details do not matter
+const SAMPLE_SIZE: u32 = 64;
+///Components along the longest side of a BlurHash, 3 along the other
+const BLURHASH_COMPONENTS: u32 = 4;
+const PALETTE_SIZE: usize = 5;
+
+fn base83(value: u32, length: u32, out: &mut String) {
+    for i in (0..length).rev() {
+        let digit = (value / 83u32.pow(i)) % 83;
+        out.push(BASE83[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+fn sign_pow(value: f64, exp: f64) -> f64 {
+    value.abs().powf(exp).copysign(value)
+}
+
+fn sample(img: &DynamicImage) -> RgbImage {
+    img.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_rgb8()
+}
+
+///BlurHash of the image, see https://blurha.sh
+fn blurhash(pixels: &RgbImage) -> String {
+    let (width, height) = pixels.dimensions();
+    let (nx, ny) = if width >= height {
+        (BLURHASH_COMPONENTS, 3)
+    } else {
+        (3, BLURHASH_COMPONENTS)
+    };
+    let mut factors = Vec::with_capacity((nx * ny) as usize);
+    for j in 0..ny {
+        for i in 0..nx {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0f64; 3];
+            for (x, y, pixel) in pixels.enumerate_pixels() {
+                let basis = (std::f64::consts::PI * i as f64 * x as f64 / width as f64).cos()
+                    * (std::f64::consts::PI * j as f64 * y as f64 / height as f64).cos();
+                for (c, value) in factor.iter_mut().enumerate() {
+                    *value += basis * srgb_to_linear(pixel[c]);
+                }
+            }
+            let scale = normalisation / (width * height) as f64;
+            factors.push(factor.map(|v| v * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    base83((nx - 1) + (ny - 1) * 9, 1, &mut hash);
+    let (dc, ac) = factors.split_first().unwrap();
+    let maximum = ac
+        .iter()
+        .flat_map(|f| f.iter())
+        .fold(0f64, |max, v| max.max(v.abs()));
+    let maximum = if ac.is_empty() {
+        base83(0, 1, &mut hash);
+        1.0
+    } else {
+        let quantised = ((maximum * 166.0 - 0.5).floor()).clamp(0.0, 82.0) as u32;
+        base83(quantised, 1, &mut hash);
+        (quantised + 1) as f64 / 166.0
+    };
+    let dc = (linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]);
+    base83(dc, 4, &mut hash);
+    for factor in ac.iter() {
+        let quantised = factor
+            .map(|v| ((sign_pow(v / maximum, 0.5) * 9.0 + 9.5).floor()).clamp(0.0, 18.0) as u32);
+        base83(
+            quantised[0] * 19 * 19 + quantised[1] * 19 + quantised[2],
+            2,
+            &mut hash,
+        );
+    }
+    hash
+}
+
+///Main colours by median cut, most present first
+fn palette(pixels: &RgbImage) -> Vec<[u8; 3]> {
+    let mut boxes: Vec<Vec<[u8; 3]>> = vec![pixels.pixels().map(|p| p.0).collect()];
+    while boxes.len() < PALETTE_SIZE {
+        //Split the box spanning the widest range of a channel at its median
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let (channel, range) = (0..3)
+                    .map(|c| {
+                        let (min, max) = b
+                            .iter()
+                            .fold((255, 0), |(min, max), p| (p[c].min(min), p[c].max(max)));
+                        (c, max - min)
+                    })
+                    .max_by_key(|(_, range)| *range)
+                    .unwrap();
+                (i, channel, range)
+            })
+            .filter(|(_, _, range)| *range > 0)
+            .max_by_key(|(_, _, range)| *range);
+        let (index, channel, _) = match widest {
+            Some(widest) => widest,
+            None => break,
+        };
+        let mut split = boxes.swap_remove(index);
+        split.sort_unstable_by_key(|p| p[channel]);
+        let upper = split.split_off(split.len() / 2);
+        boxes.push(split);
+        boxes.push(upper);
+    }
+    boxes.sort_by_key(|b| std::cmp::Reverse(b.len()));
+    boxes
+        .iter()
+        .filter(|b| !b.is_empty())
+        .map(|b| {
+            let mut sum = [0usize; 3];
+            for p in b.iter() {
+                for c in 0..3 {
+                    sum[c] += p[c] as usize;
+                }
+            }
+            sum.map(|s| (s / b.len()) as u8)
+        })
+        .collect()
+}
+
+///BlurHash and main colours as #rrggbb, shown by clients while the image loads
+pub fn placeholder(img: &DynamicImage) -> (String, Vec<String>) {
+    let pixels = sample(img);
+    let colours = palette(&pixels)
+        .iter()
+        .map(|c| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]))
+        .collect();
+    (blurhash(&pixels), colours)
+}
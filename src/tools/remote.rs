@@ -0,0 +1,272 @@
+use futures::StreamExt;
+use mime::Mime;
+use reqwest::{header, redirect::Policy, Client, Url};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::lookup_host;
+
+use crate::{config::Config, tools::ResourceIOError};
+
+const MAX_REDIRECTS: usize = 5;
+
+///Body of a remote file along with its announced type
+pub struct RemoteFile {
+    pub mime: Mime,
+    ///Last segment of the url path
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+///Whether addr is reachable from the internet.
+///Private, loopback, link local and other special purpose ranges are not
+pub fn is_public_address(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => is_public_v4(v4),
+        IpAddr::V6(v6) => match embedded_v4(v6) {
+            Some(v4) => is_public_v4(&v4),
+            None => is_public_v6(v6),
+        },
+    }
+}
+
+///IPv4 address reached through v6: mapped ::ffff:a.b.c.d, compatible ::a.b.c.d,
+///NAT64 64:ff9b::a.b.c.d and 6to4 2002:a.b.c.d::
+fn embedded_v4(addr: &Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = addr.segments();
+    let octets = addr.octets();
+    if let Some(v4) = addr.to_ipv4_mapped() {
+        return Some(v4);
+    }
+    if segments[..6] == [0; 6] && !addr.is_loopback() && !addr.is_unspecified() {
+        return Some(Ipv4Addr::new(
+            octets[12], octets[13], octets[14], octets[15],
+        ));
+    }
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return Some(Ipv4Addr::new(
+            octets[12], octets[13], octets[14], octets[15],
+        ));
+    }
+    if segments[0] == 0x2002 {
+        return Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]));
+    }
+    None
+}
+
+fn is_public_v4(addr: &Ipv4Addr) -> bool {
+    let [a, b, ..] = addr.octets();
+    !(addr.is_private()
+        || addr.is_loopback()
+        || addr.is_link_local()
+        || addr.is_broadcast()
+        || addr.is_documentation()
+        || addr.is_unspecified()
+        || addr.is_multicast()
+        //0.0.0.0/8, shared 100.64.0.0/10, benchmarking 198.18.0.0/15 and reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && b & 0xc0 == 64)
+        || (a == 198 && b & 0xfe == 18)
+        || a >= 240)
+}
+
+fn is_public_v6(addr: &Ipv6Addr) -> bool {
+    let segments = addr.segments();
+    !(addr.is_loopback()
+        || addr.is_unspecified()
+        || addr.is_multicast()
+        //Unique local fc00::/7, link local fe80::/10 and documentation 2001:db8::/32
+        || segments[0] & 0xfe00 == 0xfc00
+        || segments[0] & 0xffc0 == 0xfe80
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+///Check url can be fetched and return the addresses connections must go to,
+///None when any address is allowed
+async fn check_url(
+    url: &Url,
+    allow_private: bool,
+) -> Result<Option<Vec<SocketAddr>>, ResourceIOError> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(ResourceIOError::InvalidUpload(
+            "only http and https urls can be imported".to_string(),
+        ));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| ResourceIOError::InvalidUpload("url has no host".to_string()))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if allow_private {
+        return Ok(None);
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<_> = lookup_host((host, port))
+        .await
+        .map_err(|_| ResourceIOError::FetchFailed(format!("cannot resolve {}", host)))?
+        .collect();
+    if addrs.is_empty() || addrs.iter().any(|a| !is_public_address(&a.ip())) {
+        return Err(ResourceIOError::BlockedAddress);
+    }
+    Ok(Some(addrs))
+}
+
+fn fetch_error(e: reqwest::Error) -> ResourceIOError {
+    ResourceIOError::FetchFailed(e.to_string())
+}
+
+///Download an image or a video, refusing addresses outside of the internet unless allowed by config
+pub async fn fetch_remote(url: &str, config: &Config) -> Result<RemoteFile, ResourceIOError> {
+    let mut url = Url::parse(url)
+        .map_err(|_| ResourceIOError::InvalidUpload("url is invalid".to_string()))?;
+    let mut redirects = 0;
+    let response = loop {
+        //Redirects are followed by hand so that every target is checked, and the host is
+        //pinned to the checked address so that it cannot resolve elsewhere when connecting
+        let mut builder = Client::builder()
+            .redirect(Policy::none())
+            .no_proxy()
+            .timeout(Duration::from_secs(config.import_timeout_secs));
+        let addrs = check_url(&url, config.import_allow_private).await?;
+        if let (Some(addrs), Some(domain)) = (addrs, url.domain()) {
+            builder = builder.resolve_to_addrs(domain, &addrs);
+        }
+        let client = builder.build().map_err(fetch_error)?;
+        let response = client.get(url.clone()).send().await.map_err(fetch_error)?;
+        if let Some(addr) = response.remote_addr() {
+            if !config.import_allow_private && !is_public_address(&addr.ip()) {
+                return Err(ResourceIOError::BlockedAddress);
+            }
+        }
+        if !response.status().is_redirection() {
+            break response;
+        }
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(ResourceIOError::FetchFailed(
+                "too many redirects".to_string(),
+            ));
+        }
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .ok_or_else(|| ResourceIOError::FetchFailed("redirect without location".to_string()))?;
+        url = url
+            .join(location)
+            .map_err(|_| ResourceIOError::FetchFailed("invalid redirect".to_string()))?;
+    };
+
+    if !response.status().is_success() {
+        return Err(ResourceIOError::FetchFailed(format!(
+            "remote answered {}",
+            response.status()
+        )));
+    }
+    let mime = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .and_then(|t| t.parse::<Mime>().ok())
+        .filter(|m| m.type_() == mime::IMAGE || m.type_() == mime::VIDEO)
+        .ok_or_else(|| ResourceIOError::UnsupportedMediaType("image/* or video/*".to_string()))?;
+    let max_size = config.import_max_size as usize;
+    if response.content_length().unwrap_or(0) as usize > max_size {
+        return Err(ResourceIOError::TooLarge);
+    }
+
+    let mut data = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(fetch_error)?;
+        if data.len() + chunk.len() > max_size {
+            return Err(ResourceIOError::TooLarge);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    let filename = url
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+    Ok(RemoteFile {
+        mime,
+        filename,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_public_address;
+
+    fn public(addr: &str) -> bool {
+        is_public_address(&addr.parse().unwrap())
+    }
+
+    #[test]
+    fn public_addresses() {
+        for addr in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:4700::1111",
+            "2a00:1450:4007::200e",
+        ] {
+            assert!(public(addr), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn private_v4() {
+        for addr in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "198.18.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "240.0.0.1",
+        ] {
+            assert!(!public(addr), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn private_v6() {
+        for addr in [
+            "::1",
+            "::",
+            "fc00::1",
+            "fd12::1",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+        ] {
+            assert!(!public(addr), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn embedded_v4() {
+        for addr in [
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::127.0.0.1",
+            "::169.254.169.254",
+            "64:ff9b::7f00:1",
+            "64:ff9b::192.168.0.1",
+            "2002:7f00:1::",
+            "2002:a9fe:a9fe::1",
+        ] {
+            assert!(!public(addr), "{}", addr);
+        }
+        for addr in ["::ffff:8.8.8.8", "64:ff9b::8.8.8.8", "2002:808:808::1"] {
+            assert!(public(addr), "{}", addr);
+        }
+    }
+}
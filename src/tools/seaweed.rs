@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use mongodb::bson::{from_bson, Bson};
 use serde::{Deserialize, Serialize};
-use std::string::String;
+use std::{io, path::Path, string::String};
 
 use super::get_seaweed;
 use crate::models::{BytesStream, Identifiable, Readable, Writable};
@@ -33,6 +33,11 @@ impl Readable for SeaweedFsId {
         let stream = client.get_file(self).await;
         return stream;
     }
+
+    async fn read_range(&self, start: u64, end: u64) -> BytesStream {
+        let client = get_seaweed().await;
+        client.get_file_range(self, start, end).await
+    }
 }
 
 #[async_trait]
@@ -42,10 +47,20 @@ impl Writable for SeaweedFsId {
         client.set_file(self, data).await;
     }
 
+    async fn save_file(&self, path: &Path) -> io::Result<()> {
+        let client = get_seaweed().await;
+        client.set_file_from(self, path).await
+    }
+
     async fn alloc() -> SeaweedFsId {
         let client = get_seaweed().await;
         client.get_alloc().await
     }
+
+    async fn delete(&self) -> io::Result<()> {
+        let client = get_seaweed().await;
+        client.delete_file(self).await
+    }
 }
 
 impl Identifiable for SeaweedFsId {
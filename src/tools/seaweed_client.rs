@@ -1,11 +1,14 @@
 use cached::proc_macro::cached;
 use once_cell::sync::OnceCell;
 use reqwest::{
+    header::RANGE,
     multipart::{Form, Part},
-    Client,
+    Body, Client, StatusCode,
 };
 use serde::Deserialize;
-use tokio::sync::Mutex;
+use std::{io, path::Path};
+use tokio::{fs::File, sync::Mutex};
+use tokio_util::io::ReaderStream;
 
 use crate::models::{BytesStream, Identifiable};
 use crate::tools::SeaweedFsId;
@@ -75,6 +78,19 @@ impl SeaweedFsClient {
         Box::pin(res.bytes_stream())
     }
 
+    pub async fn get_file_range(&self, fid: &SeaweedFsId, start: u64, end: u64) -> BytesStream {
+        let addr = get_volume_addr(fid.get_volume()).await;
+        let url = format!("http://{}/{}", addr, fid.get_uid());
+        let res = self
+            .get_client()
+            .get(url)
+            .header(RANGE, format!("bytes={}-{}", start, end))
+            .send()
+            .await
+            .expect("Failed");
+        Box::pin(res.bytes_stream())
+    }
+
     pub async fn get_alloc(&self) -> SeaweedFsId {
         let url = "http://5.1.1.1:9333/dir/assign";
         let res = self.get_client().get(url).send().await.expect("Failed");
@@ -95,4 +111,37 @@ impl SeaweedFsClient {
             .await
             .expect("Cannot Upload");
     }
+
+    ///Upload the file at path chunk by chunk
+    pub async fn set_file_from(&self, fid: &SeaweedFsId, path: &Path) -> io::Result<()> {
+        let file = File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let addr = get_volume_addr(fid.get_volume()).await;
+        let part = Part::stream_with_length(Body::wrap_stream(ReaderStream::new(file)), length);
+        let form = Form::new().part("file", part);
+        self.get_client()
+            .post(format!("http://{}/{}", addr, fid.get_uid()))
+            .multipart(form)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    ///Delete the file, succeeds when it is already gone
+    pub async fn delete_file(&self, fid: &SeaweedFsId) -> io::Result<()> {
+        let addr = get_volume_addr(fid.get_volume()).await;
+        let url = format!("http://{}/{}", addr, fid.get_uid());
+        let response = self
+            .get_client()
+            .delete(url)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        if response.status() != StatusCode::NOT_FOUND {
+            response.error_for_status().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
 }
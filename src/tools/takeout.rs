@@ -0,0 +1,189 @@
+use chrono::{TimeZone, Utc};
+use mime::Mime;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::models::GeoPoint;
+
+///Name of the file describing an album folder
+const ALBUM_METADATA: &str = "metadata.json";
+const SUPPLEMENTAL_SUFFIX: &str = ".supplemental-metadata";
+const EDITED_SUFFIX: &str = "-edited";
+///Takeout truncates long sidecar names, shorter ones are only matched exactly
+const TRUNCATED_MIN_LEN: usize = 40;
+
+#[derive(Deserialize, Debug)]
+struct Timestamp {
+    ///Seconds since epoch, as a string
+    timestamp: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeoData {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl GeoData {
+    ///Takeout fills missing locations with zeroes
+    fn point(&self) -> Option<GeoPoint> {
+        if self.latitude == 0.0 && self.longitude == 0.0 {
+            return None;
+        }
+        GeoPoint::new(self.longitude, self.latitude)
+    }
+}
+
+///JSON file exported by Google Photos along each media
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sidecar {
+    ///Original name of the media
+    pub title: Option<String>,
+    pub description: Option<String>,
+    photo_taken_time: Option<Timestamp>,
+    ///Location edited in Google Photos
+    geo_data: Option<GeoData>,
+    ///Location embedded in the media
+    geo_data_exif: Option<GeoData>,
+}
+
+impl Sidecar {
+    pub fn taken_at(&self) -> Option<chrono::DateTime<Utc>> {
+        let seconds = self.photo_taken_time.as_ref()?.timestamp.parse().ok()?;
+        Utc.timestamp_opt(seconds, 0).single()
+    }
+
+    pub fn location(&self) -> Option<GeoPoint> {
+        self.geo_data
+            .as_ref()
+            .and_then(GeoData::point)
+            .or_else(|| self.geo_data_exif.as_ref().and_then(GeoData::point))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AlbumMetadata {
+    title: Option<String>,
+}
+
+///Folder and name of a path within an archive
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+///Type of media guessed from the extension of path, None for other files
+pub fn media_type(path: &str) -> Option<Mime> {
+    let (_, name) = split_path(path);
+    if name.starts_with('.') {
+        return None;
+    }
+    mime_guess::from_path(name)
+        .first()
+        .filter(|m| m.type_() == mime::IMAGE || m.type_() == mime::VIDEO)
+}
+
+///Sidecar names Google Photos gives to the media named name, most likely first
+fn sidecar_names(name: &str) -> Vec<String> {
+    let mut names = vec![
+        format!("{}.json", name),
+        format!("{}{}.json", name, SUPPLEMENTAL_SUFFIX),
+    ];
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some(parts) => parts,
+        None => return names,
+    };
+    //Copies with a same name are numbered before the extension of the media,
+    //but after the one of the sidecar: IMG(1).jpg is described by IMG.jpg(1).json
+    if let Some(open) = stem.rfind('(').filter(|_| stem.ends_with(')')) {
+        let counter = &stem[open..];
+        if counter.len() > 2
+            && counter[1..counter.len() - 1]
+                .chars()
+                .all(|c| c.is_ascii_digit())
+        {
+            let original = format!("{}.{}", &stem[..open], ext);
+            names.push(format!("{}{}.json", original, counter));
+            names.push(format!(
+                "{}{}{}.json",
+                original, SUPPLEMENTAL_SUFFIX, counter
+            ));
+        }
+    }
+    //Edited versions share the sidecar of their original
+    if let Some(original) = stem.strip_suffix(EDITED_SUFFIX) {
+        names.extend(sidecar_names(&format!("{}.{}", original, ext)));
+    }
+    names
+}
+
+///Sidecars and albums found in a Google Photos Takeout archive
+#[derive(Default)]
+pub struct TakeoutIndex {
+    ///Sidecars by folder and name
+    sidecars: HashMap<String, HashMap<String, Sidecar>>,
+    ///Sidecar name by folder and original media name
+    titles: HashMap<(String, String), String>,
+    ///Album title by folder
+    albums: HashMap<String, String>,
+}
+
+impl TakeoutIndex {
+    ///Record the JSON file at path, files that are not sidecars nor album metadata are ignored
+    pub fn add_json(&mut self, path: &str, data: &[u8]) {
+        let (folder, name) = split_path(path);
+        if name == ALBUM_METADATA {
+            if let Some(title) = serde_json::from_slice::<AlbumMetadata>(data)
+                .ok()
+                .and_then(|a| a.title)
+                .filter(|t| !t.trim().is_empty())
+            {
+                self.albums.insert(folder.to_string(), title);
+            }
+            return;
+        }
+        if let Ok(sidecar) = serde_json::from_slice::<Sidecar>(data) {
+            if let Some(title) = &sidecar.title {
+                self.titles
+                    .entry((folder.to_string(), title.clone()))
+                    .or_insert_with(|| name.to_string());
+            }
+            self.sidecars
+                .entry(folder.to_string())
+                .or_default()
+                .insert(name.to_string(), sidecar);
+        }
+    }
+
+    ///Sidecar of the media at path, matched by name, then by original name,
+    ///then by the truncated name Takeout gives to sidecars of long names
+    pub fn sidecar(&self, path: &str) -> Option<&Sidecar> {
+        let (folder, name) = split_path(path);
+        let sidecars = self.sidecars.get(folder)?;
+        if let Some(sidecar) = sidecar_names(name).iter().find_map(|n| sidecars.get(n)) {
+            return Some(sidecar);
+        }
+        if let Some(sidecar) = self
+            .titles
+            .get(&(folder.to_string(), name.to_string()))
+            .and_then(|n| sidecars.get(n))
+        {
+            return Some(sidecar);
+        }
+        let full = format!("{}{}", name, SUPPLEMENTAL_SUFFIX);
+        sidecars
+            .iter()
+            .filter_map(|(n, sidecar)| {
+                let stem = n.strip_suffix(".json")?;
+                (stem.len() >= TRUNCATED_MIN_LEN && full.starts_with(stem))
+                    .then_some((stem.len(), sidecar))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, sidecar)| sidecar)
+    }
+
+    ///Title of the album holding the media at path
+    pub fn album(&self, path: &str) -> Option<&str> {
+        self.albums.get(split_path(path).0).map(String::as_str)
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+///Failures allowed before any delay is enforced
+const FREE_ATTEMPTS: u32 = 3;
+const MAX_DELAY: Duration = Duration::from_secs(15 * 60);
+///Failures older than this are forgotten
+const RESET_AFTER: Duration = Duration::from_secs(60 * 60);
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+}
+
+impl Attempts {
+    ///Exponential backoff: 1s, 2s, 4s... once free attempts are spent
+    fn delay(&self) -> Duration {
+        if self.failures < FREE_ATTEMPTS {
+            return Duration::from_secs(0);
+        }
+        let exp = (self.failures - FREE_ATTEMPTS).min(16);
+        Duration::from_secs(1 << exp).min(MAX_DELAY)
+    }
+
+    fn retry_after(&self, now: Instant) -> Option<Duration> {
+        let allowed_at = self.last_failure + self.delay();
+        if allowed_at > now {
+            Some(allowed_at - now)
+        } else {
+            None
+        }
+    }
+}
+
+///In memory tracker of failed logins, keyed by client address and by username
+#[derive(Default)]
+pub struct LoginThrottle {
+    attempts: HashMap<String, Attempts>,
+}
+
+impl LoginThrottle {
+    fn keys(ip: &str, username: &str) -> [String; 2] {
+        [format!("ip:{}", ip), format!("user:{}", username)]
+    }
+
+    ///Seconds to wait before ip or username is allowed another attempt
+    pub fn retry_after(&self, ip: &str, username: &str) -> Option<u64> {
+        let now = Instant::now();
+        Self::keys(ip, username)
+            .iter()
+            .filter_map(|k| self.attempts.get(k))
+            .filter_map(|a| a.retry_after(now))
+            .max()
+            .map(|d| d.as_secs() + 1)
+    }
+
+    pub fn record_failure(&mut self, ip: &str, username: &str) {
+        let now = Instant::now();
+        if self.attempts.len() > PRUNE_THRESHOLD {
+            self.attempts
+                .retain(|_, a| now.duration_since(a.last_failure) < RESET_AFTER);
+        }
+        for key in Self::keys(ip, username).iter() {
+            let attempts = self.attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+            });
+            if now.duration_since(attempts.last_failure) >= RESET_AFTER {
+                attempts.failures = 0;
+            }
+            attempts.failures += 1;
+            attempts.last_failure = now;
+        }
+    }
+
+    ///Forget failures of username, those of ip are kept as it may be guessing other accounts
+    pub fn record_success(&mut self, ip: &str, username: &str) {
+        let [_, user] = Self::keys(ip, username);
+        self.attempts.remove(&user);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(throttle: &mut LoginThrottle, ip: &str, username: &str, times: u32) {
+        for _ in 0..times {
+            throttle.record_failure(ip, username);
+        }
+    }
+
+    #[test]
+    fn free_attempts() {
+        let mut throttle = LoginThrottle::default();
+        assert_eq!(throttle.retry_after("10.0.0.1", "alice"), None);
+        failed(&mut throttle, "10.0.0.1", "alice", FREE_ATTEMPTS - 1);
+        assert_eq!(throttle.retry_after("10.0.0.1", "alice"), None);
+        throttle.record_failure("10.0.0.1", "alice");
+        assert!(matches!(
+            throttle.retry_after("10.0.0.1", "alice"),
+            Some(1..=2)
+        ));
+    }
+
+    #[test]
+    fn backoff_grows_up_to_max() {
+        let mut throttle = LoginThrottle::default();
+        failed(&mut throttle, "10.0.0.1", "alice", FREE_ATTEMPTS + 2);
+        assert!(matches!(
+            throttle.retry_after("10.0.0.1", "alice"),
+            Some(4..=5)
+        ));
+        failed(&mut throttle, "10.0.0.1", "alice", 30);
+        let max = MAX_DELAY.as_secs();
+        assert!(
+            matches!(throttle.retry_after("10.0.0.1", "alice"), Some(s) if s == max || s == max + 1)
+        );
+    }
+
+    #[test]
+    fn keyed_by_ip_and_username() {
+        let mut throttle = LoginThrottle::default();
+        failed(&mut throttle, "10.0.0.1", "alice", FREE_ATTEMPTS);
+        assert!(throttle.retry_after("10.0.0.2", "alice").is_some());
+        assert!(throttle.retry_after("10.0.0.1", "bob").is_some());
+        assert_eq!(throttle.retry_after("10.0.0.2", "bob"), None);
+    }
+
+    #[test]
+    fn success_keeps_ip_failures() {
+        let mut throttle = LoginThrottle::default();
+        failed(&mut throttle, "10.0.0.1", "alice", FREE_ATTEMPTS);
+        throttle.record_success("10.0.0.1", "alice");
+        assert_eq!(throttle.retry_after("10.0.0.2", "alice"), None);
+        assert!(throttle.retry_after("10.0.0.1", "bob").is_some());
+    }
+}
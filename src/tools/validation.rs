@@ -0,0 +1,82 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::tools::ValidationError;
+
+pub const USERNAME_MIN_LEN: usize = 3;
+pub const USERNAME_MAX_LEN: usize = 32;
+pub const PASSWORD_MIN_LEN: usize = 8;
+///Bounded so that hashing a password stays cheap
+pub const PASSWORD_MAX_LEN: usize = 128;
+const EMAIL_MAX_LEN: usize = 254;
+
+///Known breached passwords, loaded from a file containing one password per line
+#[derive(Default)]
+pub struct PasswordBlocklist {
+    passwords: HashSet<String>,
+}
+
+impl PasswordBlocklist {
+    pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(Self {
+            passwords: content
+                .lines()
+                .map(|l| l.trim_end_matches('\r'))
+                .filter(|l| !l.is_empty())
+                .map(|l| l.to_string())
+                .collect(),
+        })
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        self.passwords.contains(password)
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let len = username.chars().count();
+    if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&len) {
+        return Err(ValidationError::UsernameLength(
+            USERNAME_MIN_LEN,
+            USERNAME_MAX_LEN,
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err(ValidationError::UsernameCharset);
+    }
+    Ok(())
+}
+
+pub fn validate_password(
+    password: &str,
+    blocklist: &PasswordBlocklist,
+) -> Result<(), ValidationError> {
+    let len = password.chars().count();
+    if !(PASSWORD_MIN_LEN..=PASSWORD_MAX_LEN).contains(&len) {
+        return Err(ValidationError::PasswordLength(
+            PASSWORD_MIN_LEN,
+            PASSWORD_MAX_LEN,
+        ));
+    }
+    if blocklist.contains(password) {
+        return Err(ValidationError::PasswordBreached);
+    }
+    Ok(())
+}
+
+pub fn validate_email(email: &str) -> Result<(), ValidationError> {
+    let mut parts = email.splitn(2, '@');
+    let local = parts.next().unwrap_or_default();
+    let domain = parts.next().unwrap_or_default();
+    if email.len() > EMAIL_MAX_LEN
+        || local.is_empty()
+        || !domain.contains('.')
+        || email.chars().any(char::is_whitespace)
+    {
+        return Err(ValidationError::InvalidEmail);
+    }
+    Ok(())
+}
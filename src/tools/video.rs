@@ -0,0 +1,467 @@
+use mongodb::bson::oid::ObjectId;
+use std::{io::SeekFrom, path::Path, process::Stdio, time::Duration};
+use tokio::{
+    fs,
+    io::{self, AsyncReadExt, AsyncSeekExt},
+    process::Command,
+};
+
+use crate::models::VideoInfo;
+
+const POSTER_TIMEOUT: Duration = Duration::from_secs(30);
+///Posters are taken this far into videos, or at their middle when shorter
+const POSTER_AT_SECS: f64 = 1.0;
+const POSTER_MAX_SIZE: u32 = 1280;
+///Most bytes of a staged video read to probe it
+const HEAD_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+///Boxes of an ISO base media file (MP4, MOV), yielding their type and payload
+struct Boxes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Boxes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.data;
+        let pos = self.pos;
+        if pos + 8 > data.len() {
+            return None;
+        }
+        let kind = &data[pos + 4..pos + 8];
+        let (size, header) = match be(&data[pos..pos + 4]) {
+            1 if pos + 16 <= data.len() => (be(&data[pos + 8..pos + 16]), 16),
+            1 => return None,
+            //Box extends to the end of the file
+            0 => ((data.len() - pos) as u64, 8),
+            size => (size, 8),
+        };
+        if size < header || size > (data.len() - pos) as u64 {
+            return None;
+        }
+        let end = pos + size as usize;
+        self.pos = end;
+        Some((kind, &data[pos + header as usize..end]))
+    }
+}
+
+///Big endian unsigned integer
+fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    Boxes::new(data).find(|(k, _)| k == kind).map(|(_, p)| p)
+}
+
+fn slice(data: &[u8], start: usize, len: usize) -> Option<&[u8]> {
+    data.get(start..start.checked_add(len)?)
+}
+
+///Common name of a codec given by its MP4 sample entry or Matroska codec id
+fn codec_name(codec: &str) -> String {
+    match codec {
+        "avc1" | "avc3" | "V_MPEG4/ISO/AVC" => "h264",
+        "hvc1" | "hev1" | "V_MPEGH/ISO/HEVC" => "hevc",
+        "av01" | "V_AV1" => "av1",
+        "vp08" | "V_VP8" => "vp8",
+        "vp09" | "V_VP9" => "vp9",
+        "mp4v" => "mpeg4",
+        "mp4a" | "A_AAC" => "aac",
+        "Opus" | "A_OPUS" => "opus",
+        "A_VORBIS" => "vorbis",
+        "ac-3" | "A_AC3" => "ac3",
+        other => other,
+    }
+    .to_string()
+}
+
+///Rotation of a track from its transformation matrix, in quarter turns
+fn quarter_turns(tkhd: &[u8]) -> Option<u32> {
+    let matrix = if tkhd.first()? == &1 { 52 } else { 40 };
+    let a = be(slice(tkhd, matrix, 4)?) as u32 as i32;
+    let b = be(slice(tkhd, matrix + 4, 4)?) as u32 as i32;
+    Some(match (a, b) {
+        (0, b) if b > 0 => 1,
+        (0, _) => 3,
+        (a, _) if a < 0 => 2,
+        _ => 0,
+    })
+}
+
+fn probe_bmff(data: &[u8]) -> Option<VideoInfo> {
+    let (first, payload) = Boxes::new(data).next()?;
+    let container = match first {
+        b"ftyp" if payload.starts_with(b"qt  ") => "quicktime",
+        b"ftyp" => "mp4",
+        //Older QuickTime files start without a file type
+        b"moov" | b"mdat" | b"wide" | b"free" | b"skip" => "quicktime",
+        _ => return None,
+    };
+    let moov = child(data, b"moov")?;
+    let mut info = VideoInfo {
+        container: container.to_string(),
+        ..Default::default()
+    };
+    if let Some(mvhd) = child(moov, b"mvhd") {
+        let (timescale, duration) = match mvhd.first() {
+            Some(1) => (slice(mvhd, 20, 4), slice(mvhd, 24, 8)),
+            _ => (slice(mvhd, 12, 4), slice(mvhd, 16, 4)),
+        };
+        if let (Some(timescale), Some(duration)) = (timescale.map(be), duration) {
+            //Unknown durations have every bit set
+            if timescale > 0 && duration.iter().any(|b| *b != 0xFF) {
+                info.duration = Some(be(duration) as f64 / timescale as f64);
+            }
+        }
+    }
+
+    for trak in Boxes::new(moov)
+        .filter(|(k, _)| k == b"trak")
+        .map(|(_, p)| p)
+    {
+        let mdia = match child(trak, b"mdia") {
+            Some(mdia) => mdia,
+            None => continue,
+        };
+        let handler = child(mdia, b"hdlr").and_then(|h| slice(h, 8, 4));
+        let stsd = child(mdia, b"minf")
+            .and_then(|m| child(m, b"stbl"))
+            .and_then(|s| child(s, b"stsd"));
+        //First sample entry follows version, flags and entry count
+        let codec = stsd
+            .and_then(|s| slice(s, 12, 4))
+            .map(|c| codec_name(String::from_utf8_lossy(c).trim_end()));
+        match handler {
+            Some(b"vide") if info.codec.is_none() => {
+                info.codec = codec;
+                let size = stsd.and_then(|s| slice(s, 40, 4));
+                if let Some(size) = size {
+                    info.width = Some(be(&size[..2]) as i32).filter(|w| *w > 0);
+                    info.height = Some(be(&size[2..]) as i32).filter(|h| *h > 0);
+                }
+                if child(trak, b"tkhd").and_then(quarter_turns).unwrap_or(0) % 2 == 1 {
+                    std::mem::swap(&mut info.width, &mut info.height);
+                }
+            }
+            Some(b"soun") if info.audio_codec.is_none() => info.audio_codec = codec,
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+const EBML_HEADER: u64 = 0x1A45_DFA3;
+const EBML_DOC_TYPE: u64 = 0x4282;
+const SEGMENT: u64 = 0x1853_8067;
+const INFO: u64 = 0x1549_A966;
+const TIMECODE_SCALE: u64 = 0x2A_D7B1;
+const DURATION: u64 = 0x4489;
+const TRACKS: u64 = 0x1654_AE6B;
+const TRACK_ENTRY: u64 = 0xAE;
+const TRACK_TYPE: u64 = 0x83;
+const CODEC_ID: u64 = 0x86;
+const VIDEO: u64 = 0xE0;
+const PIXEL_WIDTH: u64 = 0xB0;
+const PIXEL_HEIGHT: u64 = 0xBA;
+const CLUSTER: u64 = 0x1F43_B675;
+
+///Variable length integer of EBML, ids keep their length marker while sizes do not.
+///Returns the value, its length and whether every value bit is set
+fn vint(data: &[u8], pos: usize, keep_marker: bool) -> Option<(u64, usize, bool)> {
+    let first = *data.get(pos)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || pos + len > data.len() {
+        return None;
+    }
+    let mask = (0xFFu16 >> len) as u8;
+    let mut value = if keep_marker { first } else { first & mask } as u64;
+    let mut all_set = first & mask == mask;
+    for b in &data[pos + 1..pos + len] {
+        value = (value << 8) | *b as u64;
+        all_set &= *b == 0xFF;
+    }
+    Some((value, len, all_set))
+}
+
+///Elements of an EBML document, yielding their id and payload
+struct Elements<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Elements<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = (u64, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, id_len, _) = vint(self.data, self.pos, true)?;
+        let (size, size_len, unknown) = vint(self.data, self.pos + id_len, false)?;
+        let start = self.pos + id_len + size_len;
+        //Live streams do not know the size of their segment
+        let end = if unknown {
+            self.data.len()
+        } else {
+            start.saturating_add(size as usize).min(self.data.len())
+        };
+        self.pos = end;
+        Some((id, &self.data[start..end]))
+    }
+}
+
+fn float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_bits(be(data) as u32) as f64),
+        8 => Some(f64::from_bits(be(data))),
+        _ => None,
+    }
+}
+
+fn probe_matroska(data: &[u8]) -> Option<VideoInfo> {
+    let mut elements = Elements::new(data);
+    let header = elements
+        .next()
+        .filter(|(id, _)| *id == EBML_HEADER)
+        .map(|(_, p)| p)?;
+    let doc_type = Elements::new(header)
+        .find(|(id, _)| *id == EBML_DOC_TYPE)
+        .map(|(_, p)| {
+            String::from_utf8_lossy(p)
+                .trim_end_matches('\0')
+                .to_string()
+        })?;
+    let mut info = VideoInfo {
+        container: doc_type,
+        ..Default::default()
+    };
+    let segment = elements.find(|(id, _)| *id == SEGMENT).map(|(_, p)| p)?;
+
+    for (id, payload) in Elements::new(segment) {
+        match id {
+            INFO => {
+                let mut scale = 1_000_000;
+                let mut duration = None;
+                for (id, value) in Elements::new(payload) {
+                    match id {
+                        TIMECODE_SCALE => scale = be(value),
+                        DURATION => duration = float(value),
+                        _ => {}
+                    }
+                }
+                info.duration = duration.map(|d| d * scale as f64 / 1e9);
+            }
+            TRACKS => {
+                for (_, entry) in Elements::new(payload).filter(|(id, _)| *id == TRACK_ENTRY) {
+                    let mut kind = 0;
+                    let mut codec = None;
+                    let mut size = (None, None);
+                    for (id, value) in Elements::new(entry) {
+                        match id {
+                            TRACK_TYPE => kind = be(value),
+                            CODEC_ID => codec = Some(codec_name(&String::from_utf8_lossy(value))),
+                            VIDEO => {
+                                for (id, value) in Elements::new(value) {
+                                    match id {
+                                        PIXEL_WIDTH => size.0 = Some(be(value) as i32),
+                                        PIXEL_HEIGHT => size.1 = Some(be(value) as i32),
+                                        _ => {}
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    match kind {
+                        1 if info.codec.is_none() => {
+                            info.codec = codec;
+                            info.width = size.0;
+                            info.height = size.1;
+                        }
+                        2 if info.audio_codec.is_none() => info.audio_codec = codec,
+                        _ => {}
+                    }
+                }
+            }
+            //Metadata precedes the media data
+            CLUSTER => break,
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+///Duration, size and codecs of an MP4, QuickTime, WebM or Matroska video
+pub fn probe_video(data: &[u8]) -> Option<VideoInfo> {
+    probe_matroska(data).or_else(|| probe_bmff(data))
+}
+
+///Enough of the video at path for probe_video without reading its media data: the boxes
+///other than media data of an MP4 or QuickTime file, or the beginning of other files
+pub async fn video_head(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path).await?;
+    let length = file.metadata().await?.len();
+    let mut head = Vec::new();
+    let mut pos = 0u64;
+    let mut header = [0u8; 16];
+    while length - pos >= 8 {
+        let read = (length - pos).min(16) as usize;
+        file.seek(SeekFrom::Start(pos)).await?;
+        file.read_exact(&mut header[..read]).await?;
+        let kind = &header[4..8];
+        if pos == 0
+            && !matches!(
+                kind,
+                b"ftyp" | b"moov" | b"mdat" | b"wide" | b"free" | b"skip"
+            )
+        {
+            break;
+        }
+        let size = match be(&header[..4]) {
+            1 if read == 16 => be(&header[8..16]),
+            0 => length - pos,
+            size => size,
+        };
+        if size < 8 || size > length - pos {
+            break;
+        }
+        if kind != b"mdat" && head.len() as u64 + size <= HEAD_MAX_SIZE {
+            let start = head.len();
+            head.resize(start + size as usize, 0);
+            file.seek(SeekFrom::Start(pos)).await?;
+            file.read_exact(&mut head[start..]).await?;
+        }
+        pos += size;
+    }
+    if pos > 0 {
+        return Ok(head);
+    }
+    //Matroska metadata precedes the media data
+    let mut head = Vec::new();
+    file.seek(SeekFrom::Start(0)).await?;
+    file.take(HEAD_MAX_SIZE).read_to_end(&mut head).await?;
+    Ok(head)
+}
+
+///JPEG of a frame of the video, extracted by ffmpeg from a copy staged in dir
+pub async fn poster_frame(
+    ffmpeg: &Path,
+    dir: &Path,
+    data: &[u8],
+    info: &VideoInfo,
+) -> Option<Vec<u8>> {
+    fs::create_dir_all(dir).await.ok()?;
+    //Containers may index their frames at their end, ffmpeg needs to seek
+    let path = dir.join(format!("poster-{}", ObjectId::new().to_hex()));
+    fs::write(&path, data).await.ok()?;
+    let poster = staged_poster_frame(ffmpeg, &path, info).await;
+    let _ = fs::remove_file(&path).await;
+    poster
+}
+
+///JPEG of a frame of the video staged at path, extracted by ffmpeg.
+///ffmpeg is held to the probed container and may not open anything but path
+pub async fn staged_poster_frame(ffmpeg: &Path, path: &Path, info: &VideoInfo) -> Option<Vec<u8>> {
+    let format = match info.container.as_str() {
+        "mp4" | "quicktime" => "mov",
+        "webm" | "matroska" => "matroska",
+        _ => return None,
+    };
+    let at = info.duration.map_or(0.0, |d| POSTER_AT_SECS.min(d / 2.0));
+    let output = Command::new(ffmpeg)
+        .args(["-v", "error", "-protocol_whitelist", "file", "-f", format])
+        .args(["-ss", &format!("{:.3}", at), "-i"])
+        .arg(path)
+        .args([
+            "-frames:v",
+            "1",
+            "-vf",
+            &format!(
+                "scale={0}:{0}:force_original_aspect_ratio=decrease",
+                POSTER_MAX_SIZE
+            ),
+            "-f",
+            "image2pipe",
+            "-c:v",
+            "mjpeg",
+            "pipe:1",
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(POSTER_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() && !output.stdout.is_empty() => {
+            Some(output.stdout)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_box() {
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(&[0xFF; 8]);
+        let mut boxes = Boxes::new(&data);
+        assert!(boxes.next().is_none());
+        assert!(boxes.next().is_none());
+        assert!(probe_video(&data).is_none());
+    }
+
+    #[test]
+    fn truncated_box() {
+        let mut data = vec![0, 0, 0, 32];
+        data.extend_from_slice(b"ftypisom");
+        assert!(Boxes::new(&data).next().is_none());
+        assert!(probe_video(&data).is_none());
+    }
+
+    #[test]
+    fn boxes() {
+        let mut data = vec![0, 0, 0, 12];
+        data.extend_from_slice(b"ftypisom");
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(b"end");
+        let boxes: Vec<_> = Boxes::new(&data).collect();
+        assert_eq!(
+            boxes,
+            vec![(&b"ftyp"[..], &b"isom"[..]), (&b"free"[..], &b"end"[..])]
+        );
+    }
+
+    #[tokio::test]
+    async fn head_skips_media_data() {
+        let mut ftyp = vec![0, 0, 0, 12];
+        ftyp.extend_from_slice(b"ftypisom");
+        let mut mdat = vec![0, 0, 4, 8];
+        mdat.extend_from_slice(b"mdat");
+        mdat.resize(1032, 0xAB);
+        let mut moov = vec![0, 0, 0, 8];
+        moov.extend_from_slice(b"moov");
+        let path = std::env::temp_dir().join(format!("head-{}", ObjectId::new().to_hex()));
+        fs::write(&path, [ftyp.clone(), mdat, moov.clone()].concat())
+            .await
+            .unwrap();
+        let head = video_head(&path).await;
+        fs::remove_file(&path).await.unwrap();
+        assert_eq!(head.unwrap(), [ftyp, moov].concat());
+    }
+}
@@ -0,0 +1,105 @@
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, Pixel, Rgba, RgbaImage,
+};
+
+use crate::models::{Watermark, WatermarkPosition};
+
+///Text is drawn at this size then scaled like image marks
+const TEXT_HEIGHT: f32 = 96.0;
+///Space between the mark and the edges, relative to the smallest side of the image
+const MARGIN: f64 = 0.02;
+const SHADOW_ALPHA: f32 = 160.0;
+
+///Text in white over a dark shadow so that it reads on any background.
+///None when the font cannot be parsed
+pub fn text_mark(font: &[u8], text: &str) -> Option<RgbaImage> {
+    let font = FontRef::try_from_slice(font).ok()?;
+    let scaled = font.as_scaled(PxScale::from(TEXT_HEIGHT));
+    let mut glyphs = Vec::new();
+    let mut caret = 0f32;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(scaled.scale(), point(caret, scaled.ascent())));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+    }
+
+    let shadow = (TEXT_HEIGHT / 24.0).ceil() as i64;
+    let (width, height) = (
+        caret.ceil() as u32 + shadow as u32,
+        scaled.height().ceil() as u32 + shadow as u32,
+    );
+    let mut mark = RgbaImage::new(width, height);
+    for (offset, colour, opacity) in [(shadow, 0u8, SHADOW_ALPHA), (0, 255u8, 255.0)] {
+        for glyph in glyphs.iter().cloned() {
+            let outlined = match font.outline_glyph(glyph) {
+                Some(outlined) => outlined,
+                None => continue,
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let x = bounds.min.x as i64 + x as i64 + offset;
+                let y = bounds.min.y as i64 + y as i64 + offset;
+                if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                    return;
+                }
+                let alpha = (coverage.min(1.0) * opacity) as u8;
+                mark.get_pixel_mut(x as u32, y as u32)
+                    .blend(&Rgba([colour, colour, colour, alpha]));
+            });
+        }
+    }
+    Some(mark)
+}
+
+///Offset of a side of length inner placed within outer, 0 for the start, 1 for the middle, 2 for the end
+fn offset(outer: u32, inner: u32, margin: i64, place: u8) -> i64 {
+    let free = outer as i64 - inner as i64;
+    match place {
+        0 => margin.min(free),
+        1 => free / 2,
+        _ => (free - margin).max(0),
+    }
+    .max(0)
+}
+
+///Draw mark over img at the configured position, scaled to a share of the image width
+///and faded by the configured opacity
+pub fn apply_watermark(img: DynamicImage, mark: &RgbaImage, watermark: &Watermark) -> DynamicImage {
+    let mut img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+    let factor = (width as f64 * watermark.scale / mark.width().max(1) as f64)
+        .min(height as f64 / mark.height().max(1) as f64);
+    let mut mark = imageops::resize(
+        mark,
+        ((mark.width() as f64 * factor).round() as u32).max(1),
+        ((mark.height() as f64 * factor).round() as u32).max(1),
+        FilterType::Triangle,
+    );
+    for pixel in mark.pixels_mut() {
+        pixel[3] = (pixel[3] as f64 * watermark.opacity).round() as u8;
+    }
+
+    let (column, row) = match watermark.position {
+        WatermarkPosition::TopLeft => (0, 0),
+        WatermarkPosition::Top => (1, 0),
+        WatermarkPosition::TopRight => (2, 0),
+        WatermarkPosition::Left => (0, 1),
+        WatermarkPosition::Center => (1, 1),
+        WatermarkPosition::Right => (2, 1),
+        WatermarkPosition::BottomLeft => (0, 2),
+        WatermarkPosition::Bottom => (1, 2),
+        WatermarkPosition::BottomRight => (2, 2),
+    };
+    let margin = (width.min(height) as f64 * MARGIN).round() as i64;
+    let x = offset(width, mark.width(), margin, column);
+    let y = offset(height, mark.height(), margin, row);
+    imageops::overlay(&mut img, &mark, x, y);
+    DynamicImage::ImageRgba8(img)
+}
@@ -0,0 +1,417 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use crc32fast::Hasher;
+use flate2::read::DeflateDecoder;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+///Version 4.5, needed for ZIP64
+const VERSION: u16 = 45;
+///Sizes follow the data in a descriptor, names are UTF-8
+const FLAGS: u16 = 0x0808;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const END_LEN: usize = 22;
+const CENTRAL_HEADER_LEN: usize = 46;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+///Entries cannot reach this size as their descriptor holds 32 bits sizes
+pub const ZIP_MAX_ENTRY_SIZE: u64 = 0xFFFF_FFFF;
+
+struct CentralEntry {
+    name: String,
+    time: u16,
+    date: u16,
+    crc: u32,
+    size: u64,
+    offset: u64,
+}
+
+struct CurrentEntry {
+    entry: CentralEntry,
+    hasher: Hasher,
+}
+
+///Writer of a ZIP archive produced as a sequence of chunks, so that it can be streamed.
+///Entries are stored without compression, media being compressed already.
+///Callers emit every chunk returned, in order, and pass the data of entries through `entry_data`
+#[derive(Default)]
+pub struct ZipWriter {
+    offset: u64,
+    entries: Vec<CentralEntry>,
+    current: Option<CurrentEntry>,
+}
+
+///MS-DOS time and date, which cannot represent years before 1980
+fn dos_datetime(time: DateTime<Utc>) -> (u16, u16) {
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let dos_time = (time.hour() << 11) | (time.minute() << 5) | (time.second() / 2);
+    let dos_date = ((time.year() as u32 - 1980).min(127) << 9) | (time.month() << 5) | time.day();
+    (dos_time as u16, dos_date as u16)
+}
+
+impl ZipWriter {
+    ///Header of a new entry, the previous one must be finished
+    pub fn start_entry(&mut self, name: &str, modified: DateTime<Utc>) -> Bytes {
+        assert!(self.current.is_none(), "Previous entry is not finished");
+        let (time, date) = dos_datetime(modified);
+        let mut header = BytesMut::with_capacity(30 + name.len());
+        header.put_u32_le(LOCAL_HEADER_SIGNATURE);
+        header.put_u16_le(VERSION);
+        header.put_u16_le(FLAGS);
+        header.put_u16_le(STORED);
+        header.put_u16_le(time);
+        header.put_u16_le(date);
+        //Checksum and sizes are in the data descriptor
+        header.put_u32_le(0);
+        header.put_u32_le(0);
+        header.put_u32_le(0);
+        header.put_u16_le(name.len() as u16);
+        header.put_u16_le(0);
+        header.put_slice(name.as_bytes());
+        self.current = Some(CurrentEntry {
+            entry: CentralEntry {
+                name: name.to_string(),
+                time,
+                date,
+                crc: 0,
+                size: 0,
+                offset: self.offset,
+            },
+            hasher: Hasher::new(),
+        });
+        self.offset += header.len() as u64;
+        header.freeze()
+    }
+
+    ///Account data of the current entry
+    pub fn entry_data(&mut self, data: &[u8]) {
+        let current = self.current.as_mut().expect("No entry started");
+        current.hasher.update(data);
+        current.entry.size += data.len() as u64;
+        self.offset += data.len() as u64;
+    }
+
+    ///Data descriptor ending the current entry
+    pub fn finish_entry(&mut self) -> Bytes {
+        let CurrentEntry { mut entry, hasher } = self.current.take().expect("No entry started");
+        entry.crc = hasher.finalize();
+        let mut descriptor = BytesMut::with_capacity(16);
+        descriptor.put_u32_le(DATA_DESCRIPTOR_SIGNATURE);
+        descriptor.put_u32_le(entry.crc);
+        descriptor.put_u32_le(entry.size as u32);
+        descriptor.put_u32_le(entry.size as u32);
+        self.offset += descriptor.len() as u64;
+        self.entries.push(entry);
+        descriptor.freeze()
+    }
+
+    ///Central directory ending the archive
+    pub fn finish(self) -> Bytes {
+        let mut directory = BytesMut::new();
+        for entry in self.entries.iter() {
+            let zip64 = entry.offset >= ZIP_MAX_ENTRY_SIZE;
+            directory.put_u32_le(CENTRAL_HEADER_SIGNATURE);
+            directory.put_u16_le(VERSION);
+            directory.put_u16_le(VERSION);
+            directory.put_u16_le(FLAGS);
+            directory.put_u16_le(STORED);
+            directory.put_u16_le(entry.time);
+            directory.put_u16_le(entry.date);
+            directory.put_u32_le(entry.crc);
+            directory.put_u32_le(entry.size as u32);
+            directory.put_u32_le(entry.size as u32);
+            directory.put_u16_le(entry.name.len() as u16);
+            directory.put_u16_le(if zip64 { 12 } else { 0 });
+            //Comment, disk number and attributes
+            directory.put_u16_le(0);
+            directory.put_u16_le(0);
+            directory.put_u16_le(0);
+            directory.put_u32_le(0);
+            directory.put_u32_le(entry.offset.min(ZIP_MAX_ENTRY_SIZE) as u32);
+            directory.put_slice(entry.name.as_bytes());
+            if zip64 {
+                directory.put_u16_le(ZIP64_EXTRA_ID);
+                directory.put_u16_le(8);
+                directory.put_u64_le(entry.offset);
+            }
+        }
+
+        let directory_offset = self.offset;
+        let directory_size = directory.len() as u64;
+        let count = self.entries.len() as u64;
+        let zip64 = directory_offset >= ZIP_MAX_ENTRY_SIZE || count >= 0xFFFF;
+        if zip64 {
+            let end_offset = directory_offset + directory_size;
+            directory.put_u32_le(ZIP64_END_SIGNATURE);
+            directory.put_u64_le(44);
+            directory.put_u16_le(VERSION);
+            directory.put_u16_le(VERSION);
+            directory.put_u32_le(0);
+            directory.put_u32_le(0);
+            directory.put_u64_le(count);
+            directory.put_u64_le(count);
+            directory.put_u64_le(directory_size);
+            directory.put_u64_le(directory_offset);
+            directory.put_u32_le(ZIP64_LOCATOR_SIGNATURE);
+            directory.put_u32_le(0);
+            directory.put_u64_le(end_offset);
+            directory.put_u32_le(1);
+        }
+        directory.put_u32_le(END_SIGNATURE);
+        directory.put_u16_le(0);
+        directory.put_u16_le(0);
+        directory.put_u16_le(count.min(0xFFFF) as u16);
+        directory.put_u16_le(count.min(0xFFFF) as u16);
+        directory.put_u32_le(directory_size.min(ZIP_MAX_ENTRY_SIZE) as u32);
+        directory.put_u32_le(directory_offset.min(ZIP_MAX_ENTRY_SIZE) as u32);
+        directory.put_u16_le(0);
+        directory.freeze()
+    }
+}
+
+///Entry listed in the central directory of an archive
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    ///Uncompressed size in bytes
+    pub size: u64,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    offset: u64,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+///Reader of ZIP archives whose entries are stored or deflated
+pub struct ZipReader<R> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn le16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+fn le32(buf: &[u8], pos: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[pos..pos + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn le64(buf: &[u8], pos: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[pos..pos + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+///Replace sizes and offset saturated in a central header by their ZIP64 value
+fn read_zip64_extra(extra: &[u8], entry: &mut ZipEntry) {
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let id = le16(extra, pos);
+        let len = le16(extra, pos + 2) as usize;
+        let end = (pos + 4 + len).min(extra.len());
+        if id == ZIP64_EXTRA_ID {
+            let mut field = pos + 4;
+            for value in [
+                &mut entry.size,
+                &mut entry.compressed_size,
+                &mut entry.offset,
+            ] {
+                if *value == ZIP_MAX_ENTRY_SIZE && field + 8 <= end {
+                    *value = le64(extra, field);
+                    field += 8;
+                }
+            }
+        }
+        pos = end;
+    }
+}
+
+impl<R: Read + Seek> ZipReader<R> {
+    ///Read the central directory of the archive
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        //End record is followed by a comment of at most 64KiB
+        let tail_len = len.min((END_LEN + 0xFFFF) as u64);
+        if tail_len < END_LEN as u64 {
+            return Err(invalid("archive is too short"));
+        }
+        reader.seek(SeekFrom::Start(len - tail_len))?;
+        let mut tail = vec![0u8; tail_len as usize];
+        reader.read_exact(&mut tail)?;
+        let end = (0..=tail.len() - END_LEN)
+            .rev()
+            .find(|&i| le32(&tail, i) == END_SIGNATURE)
+            .ok_or_else(|| invalid("end of central directory is missing"))?;
+
+        let mut count = le16(&tail, end + 10) as u64;
+        let mut directory_size = le32(&tail, end + 12) as u64;
+        let mut directory_offset = le32(&tail, end + 16) as u64;
+        //ZIP64 locator precedes the end record
+        if end >= 20 && le32(&tail, end - 20) == ZIP64_LOCATOR_SIGNATURE {
+            reader.seek(SeekFrom::Start(le64(&tail, end - 12)))?;
+            let mut record = [0u8; 56];
+            reader.read_exact(&mut record)?;
+            if le32(&record, 0) != ZIP64_END_SIGNATURE {
+                return Err(invalid("ZIP64 end of central directory is missing"));
+            }
+            count = le64(&record, 32);
+            directory_size = le64(&record, 40);
+            directory_offset = le64(&record, 48);
+        }
+        if directory_offset.saturating_add(directory_size) > len {
+            return Err(invalid("central directory is out of the archive"));
+        }
+
+        reader.seek(SeekFrom::Start(directory_offset))?;
+        let mut directory = vec![0u8; directory_size as usize];
+        reader.read_exact(&mut directory)?;
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while (entries.len() as u64) < count {
+            if pos + CENTRAL_HEADER_LEN > directory.len()
+                || le32(&directory, pos) != CENTRAL_HEADER_SIGNATURE
+            {
+                return Err(invalid("central directory is truncated"));
+            }
+            let name_len = le16(&directory, pos + 28) as usize;
+            let extra_len = le16(&directory, pos + 30) as usize;
+            let comment_len = le16(&directory, pos + 32) as usize;
+            let name_start = pos + CENTRAL_HEADER_LEN;
+            let extra_start = name_start + name_len;
+            let next = extra_start + extra_len + comment_len;
+            if next > directory.len() {
+                return Err(invalid("central directory is truncated"));
+            }
+            let mut entry = ZipEntry {
+                name: String::from_utf8_lossy(&directory[name_start..extra_start]).into_owned(),
+                size: le32(&directory, pos + 24) as u64,
+                method: le16(&directory, pos + 10),
+                crc: le32(&directory, pos + 16),
+                compressed_size: le32(&directory, pos + 20) as u64,
+                offset: le32(&directory, pos + 42) as u64,
+            };
+            read_zip64_extra(&directory[extra_start..extra_start + extra_len], &mut entry);
+            entries.push(entry);
+            pos = next;
+        }
+        Ok(Self { reader, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    ///Uncompressed data of entry, refused when larger than limit.
+    ///Sizes and checksum are checked so that an entry cannot inflate past what it declares
+    pub fn read(&mut self, entry: &ZipEntry, limit: u64) -> io::Result<Vec<u8>> {
+        if entry.size > limit {
+            return Err(invalid("entry is too large"));
+        }
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut header = [0u8; 30];
+        self.reader.read_exact(&mut header)?;
+        if le32(&header, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(invalid("local header is missing"));
+        }
+        let skip = le16(&header, 26) as i64 + le16(&header, 28) as i64;
+        self.reader.seek(SeekFrom::Current(skip))?;
+
+        let compressed = self.reader.by_ref().take(entry.compressed_size);
+        let mut data = Vec::with_capacity(entry.size as usize);
+        match entry.method {
+            STORED => compressed.take(entry.size + 1).read_to_end(&mut data)?,
+            DEFLATED => DeflateDecoder::new(compressed)
+                .take(entry.size + 1)
+                .read_to_end(&mut data)?,
+            _ => return Err(invalid("compression method is not supported")),
+        };
+        let mut hasher = Hasher::new();
+        hasher.update(&data);
+        if data.len() as u64 != entry.size || hasher.finalize() != entry.crc {
+            return Err(invalid("entry is corrupted"));
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::default();
+        let mut out = Vec::new();
+        for (name, data) in files {
+            out.extend_from_slice(&writer.start_entry(name, Utc::now()));
+            writer.entry_data(data);
+            out.extend_from_slice(data);
+            out.extend_from_slice(&writer.finish_entry());
+        }
+        out.extend_from_slice(&writer.finish());
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = archive(&[
+            ("a.jpg", b"first"),
+            ("dir/b.png", b""),
+            ("c.gif", &[7; 1000]),
+        ]);
+        let mut reader = ZipReader::new(Cursor::new(data)).unwrap();
+        let entries = reader.entries().to_vec();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["a.jpg", "dir/b.png", "c.gif"]);
+        assert_eq!(reader.read(&entries[0], 100).unwrap(), b"first");
+        assert!(reader.read(&entries[1], 100).unwrap().is_empty());
+        assert_eq!(reader.read(&entries[2], 1000).unwrap(), vec![7; 1000]);
+    }
+
+    #[test]
+    fn oversized_entry_is_rejected() {
+        let data = archive(&[("a.jpg", &[1; 100])]);
+        let mut reader = ZipReader::new(Cursor::new(data)).unwrap();
+        let entry = reader.entries()[0].clone();
+        assert!(reader.read(&entry, 99).is_err());
+    }
+
+    #[test]
+    fn truncated_archive_is_rejected() {
+        let data = archive(&[("a.jpg", &[1; 100])]);
+        for len in [0, 10, data.len() - 1] {
+            assert!(ZipReader::new(Cursor::new(&data[..len])).is_err());
+        }
+    }
+
+    #[test]
+    fn entry_shorter_than_declared_is_rejected() {
+        let mut data = archive(&[("a.jpg", &[1; 100])]);
+        let end = data.len() - END_LEN;
+        //Declare the entry one byte larger than its data
+        let directory_offset = le32(&data, end + 16) as usize;
+        let size = le32(&data, directory_offset + 24) + 1;
+        data[directory_offset + 24..directory_offset + 28].copy_from_slice(&size.to_le_bytes());
+        let mut reader = ZipReader::new(Cursor::new(data)).unwrap();
+        let entry = reader.entries()[0].clone();
+        assert!(reader.read(&entry, 1000).is_err());
+    }
+}